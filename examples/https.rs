@@ -11,21 +11,20 @@ use async_http_client::prelude::*;
 use async_http_client::{HttpRequest, HttpCodec};
 
 use self::native_tls::TlsConnector;
-use self::tokio_tls::TlsConnectorExt;
 
 fn main() {
-    let hostname = "https://www.google.com";
-    let req = HttpRequest::get(hostname).unwrap();
+    let hostname = "www.google.com";
+    let req = HttpRequest::get("https://www.google.com").unwrap();
     let mut core = Core::new().unwrap();
     let addr = req.addr().unwrap();
 
     let handle = core.handle();
-    let cx = TlsConnector::builder().unwrap().build().unwrap();
+    let cx = tokio_tls::TlsConnector::from(TlsConnector::new().unwrap());
 
     let connection = TcpStream::connect(&addr, &handle);
 
     let tls_handshake = connection.and_then(|socket| {
-        cx.connect_async(hostname, socket).map_err(|e| {
+        cx.connect(hostname, socket).map_err(|e| {
             Error::new(ErrorKind::Other, e)
         })
     });
@@ -0,0 +1,377 @@
+//! HTTP authentication.
+//!
+//! Supports the Basic (RFC 7617) and Bearer (RFC 6750) schemes, for which credentials
+//! are sent preemptively, and the Digest scheme (RFC 7616), which answers a challenge
+//! sent by the server in a `WWW-Authenticate` header.
+
+use std::fmt::Write;
+
+use base64;
+use md5;
+use rand;
+use sha2::{Digest as Sha2Digest, Sha256};
+
+use Method;
+
+/// Returns the value of an `Authorization` header for the Basic scheme.
+pub fn basic(username: &str, password: &str) -> String {
+    format!("Basic {}", base64::encode(&format!("{}:{}", username, password)))
+}
+
+/// Returns the value of an `Authorization` header for the Bearer scheme.
+pub fn bearer(token: &str) -> String {
+    format!("Bearer {}", token)
+}
+
+/// Hash algorithm of a Digest challenge.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Algorithm {
+    Md5,
+    Md5Sess,
+    Sha256,
+    Sha256Sess,
+}
+
+impl Algorithm {
+    fn parse(name: &str) -> Option<Algorithm> {
+        match name.to_ascii_uppercase().as_str() {
+            "MD5" => Some(Algorithm::Md5),
+            "MD5-SESS" => Some(Algorithm::Md5Sess),
+            "SHA-256" => Some(Algorithm::Sha256),
+            "SHA-256-SESS" => Some(Algorithm::Sha256Sess),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            Algorithm::Md5 => "MD5",
+            Algorithm::Md5Sess => "MD5-sess",
+            Algorithm::Sha256 => "SHA-256",
+            Algorithm::Sha256Sess => "SHA-256-sess",
+        }
+    }
+
+    fn is_session(&self) -> bool {
+        *self == Algorithm::Md5Sess || *self == Algorithm::Sha256Sess
+    }
+
+    fn hash(&self, data: &str) -> String {
+        self.hash_bytes(data.as_bytes())
+    }
+
+    fn hash_bytes(&self, data: &[u8]) -> String {
+        let mut hex = String::new();
+        match *self {
+            Algorithm::Md5 | Algorithm::Md5Sess => {
+                write!(hex, "{:x}", md5::compute(data)).unwrap();
+            }
+            Algorithm::Sha256 | Algorithm::Sha256Sess => {
+                for byte in Sha256::digest(data).iter() {
+                    write!(hex, "{:02x}", byte).unwrap();
+                }
+            }
+        }
+        hex
+    }
+}
+
+/// Quality of protection of a Digest challenge.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Qop {
+    Auth,
+    AuthInt,
+}
+
+/// Digest challenge, as sent by a server in a `WWW-Authenticate` header.
+#[derive(Clone, Debug)]
+pub struct Digest {
+    realm: String,
+    nonce: String,
+    opaque: Option<String>,
+    algorithm: Algorithm,
+    qop: Option<Qop>,
+    userhash: bool,
+}
+
+impl Digest {
+    /// Parses the Digest challenge contained in the value of a `WWW-Authenticate` header.
+    ///
+    /// Returns `None` if the header does not contain a Digest challenge, or if it
+    /// uses an unsupported algorithm or quality of protection.
+    pub fn parse(header: &str) -> Option<Digest> {
+        let params = challenge_params(header, "Digest")?;
+
+        let param = |name: &str| {
+            params.iter().find(|&&(ref key, _)| key == name).map(|&(_, ref value)| value.clone())
+        };
+
+        let algorithm = match param("algorithm") {
+            Some(name) => Algorithm::parse(&name)?,
+            None => Algorithm::Md5,
+        };
+
+        // prefer auth over auth-int, as the latter requires the body to be hashed
+        let qop = match param("qop") {
+            Some(qops) => {
+                let qops = qops.split(',').map(|qop| qop.trim()).collect::<Vec<_>>();
+                if qops.iter().any(|qop| qop.eq_ignore_ascii_case("auth")) {
+                    Some(Qop::Auth)
+                } else if qops.iter().any(|qop| qop.eq_ignore_ascii_case("auth-int")) {
+                    Some(Qop::AuthInt)
+                } else {
+                    return None;
+                }
+            }
+            None => None,
+        };
+
+        Some(Digest {
+            realm: param("realm").unwrap_or_default(),
+            nonce: param("nonce")?,
+            opaque: param("opaque"),
+            algorithm: algorithm,
+            qop: qop,
+            userhash: param("userhash").map_or(false, |value| value.eq_ignore_ascii_case("true")),
+        })
+    }
+
+    /// Returns the realm of this challenge.
+    pub fn realm(&self) -> &str {
+        &self.realm
+    }
+
+    /// Returns the algorithm of this challenge.
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// Returns the value of an `Authorization` header answering this challenge for
+    /// a request with the given method, request-target and body.
+    pub fn authorization(
+        &self,
+        username: &str,
+        password: &str,
+        method: &Method,
+        uri: &str,
+        body: &[u8],
+    ) -> String {
+        let cnonce = format!("{:016x}{:016x}", rand::random::<u64>(), rand::random::<u64>());
+        self.authorization_with_cnonce(username, password, method, uri, body, &cnonce)
+    }
+
+    fn authorization_with_cnonce(
+        &self,
+        username: &str,
+        password: &str,
+        method: &Method,
+        uri: &str,
+        body: &[u8],
+        cnonce: &str,
+    ) -> String {
+        let algorithm = self.algorithm;
+        let nc = "00000001";
+
+        let mut ha1 = algorithm.hash(&format!("{}:{}:{}", username, self.realm, password));
+        if algorithm.is_session() {
+            ha1 = algorithm.hash(&format!("{}:{}:{}", ha1, self.nonce, cnonce));
+        }
+
+        let ha2 = if self.qop == Some(Qop::AuthInt) {
+            format!("{}:{}:{}", method, uri, algorithm.hash_bytes(body))
+        } else {
+            format!("{}:{}", method, uri)
+        };
+        let ha2 = algorithm.hash(&ha2);
+
+        let response = match self.qop {
+            Some(qop) => {
+                let qop = if qop == Qop::Auth { "auth" } else { "auth-int" };
+                algorithm.hash(&format!("{}:{}:{}:{}:{}:{}", ha1, self.nonce, nc, cnonce, qop, ha2))
+            }
+            None => algorithm.hash(&format!("{}:{}:{}", ha1, self.nonce, ha2)),
+        };
+
+        let username = if self.userhash {
+            algorithm.hash(&format!("{}:{}", username, self.realm))
+        } else {
+            username.to_string()
+        };
+
+        let mut header = format!(
+            "Digest username={}, realm={}, uri={}, algorithm={}, nonce={}",
+            quote(&username),
+            quote(&self.realm),
+            quote(uri),
+            algorithm.name(),
+            quote(&self.nonce)
+        );
+        if let Some(qop) = self.qop {
+            let qop = if qop == Qop::Auth { "auth" } else { "auth-int" };
+            write!(header, ", nc={}, cnonce={}, qop={}", nc, quote(cnonce), qop).unwrap();
+        }
+        write!(header, ", response={}", quote(&response)).unwrap();
+        if let Some(ref opaque) = self.opaque {
+            write!(header, ", opaque={}", quote(opaque)).unwrap();
+        }
+        if self.userhash {
+            header.push_str(", userhash=true");
+        }
+        header
+    }
+}
+
+fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Returns the parameters of the challenge with the given scheme, with lowercase names.
+///
+/// A header value may contain several challenges, e.g.
+/// `Basic realm="api", Digest realm="api", nonce="abc"`.
+fn challenge_params(header: &str, scheme: &str) -> Option<Vec<(String, String)>> {
+    let bytes = header.as_bytes();
+    let mut pos = 0;
+    let mut params: Option<Vec<(String, String)>> = None;
+
+    let is_delimiter = |c: u8| c == b' ' || c == b'\t' || c == b',' || c == b'=';
+
+    loop {
+        while pos < bytes.len() && (bytes[pos] == b' ' || bytes[pos] == b'\t' || bytes[pos] == b',') {
+            pos += 1;
+        }
+        if pos == bytes.len() {
+            break;
+        }
+
+        let start = pos;
+        while pos < bytes.len() && !is_delimiter(bytes[pos]) {
+            pos += 1;
+        }
+        let token = &header[start..pos];
+
+        while pos < bytes.len() && (bytes[pos] == b' ' || bytes[pos] == b'\t') {
+            pos += 1;
+        }
+
+        if pos < bytes.len() && bytes[pos] == b'=' {
+            // auth-param
+            pos += 1;
+            while pos < bytes.len() && (bytes[pos] == b' ' || bytes[pos] == b'\t') {
+                pos += 1;
+            }
+
+            let mut value = String::new();
+            if pos < bytes.len() && bytes[pos] == b'"' {
+                pos += 1;
+                let mut chars = header[pos..].chars();
+                while let Some(c) = chars.next() {
+                    pos += c.len_utf8();
+                    match c {
+                        '"' => break,
+                        '\\' => if let Some(c) = chars.next() {
+                            pos += c.len_utf8();
+                            value.push(c);
+                        },
+                        c => value.push(c),
+                    }
+                }
+            } else {
+                let start = pos;
+                while pos < bytes.len() && !is_delimiter(bytes[pos]) {
+                    pos += 1;
+                }
+                value.push_str(&header[start..pos]);
+            }
+
+            if let Some(ref mut params) = params {
+                params.push((token.to_ascii_lowercase(), value));
+            }
+        } else if params.is_some() {
+            // start of the next challenge
+            break;
+        } else if token.eq_ignore_ascii_case(scheme) {
+            params = Some(vec![]);
+        }
+    }
+
+    params
+}
+
+#[cfg(test)]
+mod tests {
+    use Method;
+    use super::{Algorithm, Digest, basic, bearer};
+
+    #[test]
+    fn basic_and_bearer() {
+        assert_eq!(basic("Aladdin", "open sesame"), "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==");
+        assert_eq!(bearer("mF_9.B5f-4.1JqM"), "Bearer mF_9.B5f-4.1JqM");
+    }
+
+    #[test]
+    fn digest_rfc2617() {
+        let digest = Digest::parse(
+            "Digest realm=\"testrealm@host.com\", qop=\"auth,auth-int\", \
+             nonce=\"dcd98b7102dd2f0e8b11d0f600bfb0c093\", \
+             opaque=\"5ccc069c403ebaf9f0171e9517f40e41\"",
+        ).unwrap();
+        assert_eq!(digest.algorithm(), Algorithm::Md5);
+
+        let header = digest.authorization_with_cnonce(
+            "Mufasa",
+            "Circle Of Life",
+            &Method::Get,
+            "/dir/index.html",
+            b"",
+            "0a4f113b",
+        );
+        assert!(header.contains("response=\"6629fae49393a05397450978507c4ef1\""));
+        assert!(header.contains("opaque=\"5ccc069c403ebaf9f0171e9517f40e41\""));
+        assert!(header.contains("nc=00000001, cnonce=\"0a4f113b\", qop=auth"));
+    }
+
+    #[test]
+    fn digest_rfc7616() {
+        let header = "Digest realm=\"http-auth@example.org\", qop=\"auth, auth-int\", \
+                      algorithm=SHA-256, \
+                      nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", \
+                      opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\", \
+                      Digest realm=\"http-auth@example.org\", qop=\"auth, auth-int\", \
+                      algorithm=MD5, \
+                      nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", \
+                      opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\"";
+        let digest = Digest::parse(header).unwrap();
+        assert_eq!(digest.algorithm(), Algorithm::Sha256);
+        assert_eq!(digest.realm(), "http-auth@example.org");
+
+        let header = digest.authorization_with_cnonce(
+            "Mufasa",
+            "Circle of Life",
+            &Method::Get,
+            "/dir/index.html",
+            b"",
+            "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ",
+        );
+        assert!(header.contains(
+            "response=\"753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1\"",
+        ));
+    }
+
+    #[test]
+    fn digest_among_challenges() {
+        let digest = Digest::parse("Basic realm=\"api\", Digest realm=\"api\", nonce=\"abc\"").unwrap();
+        assert_eq!(digest.realm(), "api");
+        assert!(Digest::parse("Basic realm=\"api\"").is_none());
+        assert!(Digest::parse("Digest realm=\"api\", nonce=\"abc\", algorithm=SHA-512").is_none());
+    }
+}
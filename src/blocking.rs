@@ -0,0 +1,141 @@
+//! Blocking HTTP client, for synchronous code.
+//!
+//! ```no-run
+//! let client = blocking::Client::new().unwrap();
+//! let res = client.get("http://www.google.com").unwrap();
+//! ```
+
+use std::io::{Error, ErrorKind};
+use std::sync::mpsc as std_mpsc;
+use std::thread::{self, JoinHandle};
+
+use futures::{Future, Stream};
+use futures::sync::{mpsc, oneshot};
+
+use tokio_core::reactor::{Core, Handle};
+
+use connect::Connect;
+use {HttpRequest, HttpResponse};
+
+type Message = (HttpRequest, oneshot::Sender<Result<HttpResponse, Error>>);
+
+/// HTTP client that blocks the calling thread until a response is received.
+///
+/// Requests are sent by an asynchronous `Client` running on a background reactor
+/// thread, which stops when this client is dropped. Each blocking client has its own
+/// thread and `Client`, and shares no connections or configuration with the others.
+pub struct Client {
+    sender: Option<mpsc::UnboundedSender<Message>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Client {
+    /// Creates a new blocking client, backed by a `Client` that uses plain TCP connections.
+    pub fn new() -> Result<Client, Error> {
+        Client::with_client(::Client::new)
+    }
+
+    /// Creates a new blocking client, backed by the client that `build` returns.
+    ///
+    /// `build` is called once on the new reactor thread, and may configure the client
+    /// and its connector with the reactor's handle. The client it returns is only used
+    /// by this blocking client.
+    pub fn with_client<F, C>(build: F) -> Result<Client, Error>
+    where
+        F: 'static + FnOnce(&Handle) -> ::Client<C> + Send,
+        C: 'static + Connect,
+    {
+        let (sender, receiver) = mpsc::unbounded::<Message>();
+        let (ready, started) = std_mpsc::channel();
+        let thread = thread::Builder::new().name("http-client".to_string()).spawn(move || {
+            let mut core = match Core::new() {
+                Ok(core) => core,
+                Err(e) => return ready.send(Err(e)).unwrap(),
+            };
+            let handle = core.handle();
+            let client = build(&handle);
+            ready.send(Ok(())).unwrap();
+
+            // runs until all senders are dropped
+            let _ = core.run(receiver.for_each(|(req, response)| {
+                handle.spawn(client.request(req).then(|result| {
+                    let _ = response.send(result);
+                    Ok(())
+                }));
+                Ok(())
+            }));
+        })?;
+
+        started
+            .recv()
+            .map_err(|_| Error::other("reactor thread panicked"))??;
+        Ok(Client {
+            sender: Some(sender),
+            thread: Some(thread),
+        })
+    }
+
+    /// Sends a GET request to the given URL, and returns the response.
+    pub fn get<U: AsRef<str>>(&self, url: U) -> Result<HttpResponse, Error> {
+        let req = HttpRequest::get(url).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        self.request(req)
+    }
+
+    /// Sends a POST request with the given body to the given URL, and returns the response.
+    pub fn post<U: AsRef<str>, I: Into<Vec<u8>>>(&self, url: U, body: I) -> Result<HttpResponse, Error> {
+        let req = HttpRequest::post(url, body).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        self.request(req)
+    }
+
+    /// Sends the given request, and returns the response.
+    pub fn request(&self, req: HttpRequest) -> Result<HttpResponse, Error> {
+        let stopped = || Error::other("reactor thread stopped");
+        let (response, receiver) = oneshot::channel();
+        self.sender
+            .as_ref()
+            .unwrap()
+            .unbounded_send((req, response))
+            .map_err(|_| stopped())?;
+        receiver.wait().map_err(|_| stopped())?
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        // dropping the sender stops the reactor thread
+        self.sender.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+    use std::sync::{Arc, Mutex};
+
+    use mock::MockConnector;
+    use super::Client;
+
+    #[test]
+    fn blocking() {
+        let output = Arc::new(Mutex::new(vec![]));
+        let connector_output = output.clone();
+        let client = Client::with_client(move |_| {
+            ::Client::with_connector(MockConnector::new(
+                vec![
+                    b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok",
+                    b"HTTP/1.1 201 Created\r\nContent-Length: 0\r\n\r\n",
+                ],
+                &connector_output,
+            ))
+        }).unwrap();
+
+        assert_eq!(client.get("http://example.com/").unwrap().get_body(), b"ok");
+        assert_eq!(client.post("http://example.com/items", "item").unwrap().status(), 201);
+        assert!(output.lock().unwrap().ends_with(b"\r\n\r\nitem"));
+
+        assert_eq!(client.get("not a url").unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,407 @@
+//! Streaming request and response bodies.
+
+use std::cmp;
+use std::fs::File;
+use std::io::{Error, ErrorKind};
+use std::mem;
+use std::path::PathBuf;
+
+use futures::{Async, Future, Poll, Stream};
+use futures::future::{self, Either};
+
+use bytes::{Bytes, BytesMut};
+
+use tokio_io::{IoFuture, AsyncRead, AsyncWrite};
+use tokio_io::io::{flush, write_all, AllowStdIo};
+
+use nom::IResult;
+
+use parser;
+use {HttpResponse, Method};
+
+pub use framing::{Decoded, Framing};
+
+/// Stream of chunks making up a request or response body.
+pub type BodyStream = Box<dyn Stream<Item = Bytes, Error = Error> + Send>;
+
+const CHUNK_SIZE: usize = 8192;
+
+/// Returns a stream of the bytes read from `reader` until end of file.
+pub fn read_stream<R>(reader: R) -> BodyStream
+where
+    R: 'static + AsyncRead + Send,
+{
+    Box::new(ReadStream { reader: reader })
+}
+
+struct ReadStream<R> {
+    reader: R,
+}
+
+impl<R: AsyncRead> Stream for ReadStream<R> {
+    type Item = Bytes;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Bytes>, Error> {
+        let mut buf = vec![0; CHUNK_SIZE];
+        match self.reader.poll_read(&mut buf)? {
+            Async::Ready(0) => Ok(Async::Ready(None)),
+            Async::Ready(len) => {
+                buf.truncate(len);
+                Ok(Async::Ready(Some(buf.into())))
+            }
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
+
+/// Returns a stream of the content of the file at `path`, which is only opened
+/// when the stream is first polled.
+pub fn read_file(path: PathBuf) -> BodyStream {
+    Box::new(future::lazy(move || File::open(path))
+        .map(|file| read_stream(AllowStdIo::new(file)))
+        .flatten_stream())
+}
+
+/// Progress of a transfer.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Progress {
+    transferred: u64,
+    total: Option<u64>,
+}
+
+impl Progress {
+    /// Returns the number of bytes transferred so far.
+    pub fn transferred(&self) -> u64 {
+        self.transferred
+    }
+
+    /// Returns the total number of bytes to transfer, if known.
+    pub fn total(&self) -> Option<u64> {
+        self.total
+    }
+}
+
+/// Returns a stream of the chunks of `stream` that calls `callback` with the
+/// progress of the transfer after each chunk.
+pub fn with_progress<F>(stream: BodyStream, total: Option<u64>, mut callback: F) -> BodyStream
+where
+    F: 'static + FnMut(Progress) + Send,
+{
+    let mut progress = Progress {
+        transferred: 0,
+        total: total,
+    };
+    Box::new(stream.map(move |chunk| {
+        progress.transferred += chunk.len() as u64;
+        callback(progress);
+        chunk
+    }))
+}
+
+/// Returns a stream of the chunks of `stream` that keeps up to `limit` bytes of them,
+/// and calls `done` with these bytes and the length of the body once it ends, or with
+/// the error if it fails or is dropped before its end.
+///
+/// An error returned by `done` at the end of the body is the error of the stream.
+pub fn capture<F>(stream: BodyStream, limit: usize, done: F) -> BodyStream
+where
+    F: 'static + FnOnce(Result<(Vec<u8>, u64), &Error>) -> Result<(), Error> + Send,
+{
+    Box::new(Capture {
+        stream: stream,
+        bytes: vec![],
+        len: 0,
+        limit: limit,
+        done: Some(done),
+    })
+}
+
+struct Capture<F>
+where
+    F: FnOnce(Result<(Vec<u8>, u64), &Error>) -> Result<(), Error>,
+{
+    stream: BodyStream,
+    bytes: Vec<u8>,
+    len: u64,
+    limit: usize,
+    done: Option<F>,
+}
+
+impl<F> Stream for Capture<F>
+where
+    F: FnOnce(Result<(Vec<u8>, u64), &Error>) -> Result<(), Error>,
+{
+    type Item = Bytes;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Bytes>, Error> {
+        match self.stream.poll() {
+            Ok(Async::Ready(Some(chunk))) => {
+                let keep = cmp::min(chunk.len(), self.limit - self.bytes.len());
+                self.bytes.extend_from_slice(&chunk[..keep]);
+                self.len += chunk.len() as u64;
+                Ok(Async::Ready(Some(chunk)))
+            }
+            Ok(Async::Ready(None)) => {
+                if let Some(done) = self.done.take() {
+                    done(Ok((mem::take(&mut self.bytes), self.len)))?;
+                }
+                Ok(Async::Ready(None))
+            }
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(e) => {
+                if let Some(done) = self.done.take() {
+                    let _ = done(Err(&e));
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<F> Drop for Capture<F>
+where
+    F: FnOnce(Result<(Vec<u8>, u64), &Error>) -> Result<(), Error>,
+{
+    fn drop(&mut self) {
+        if let Some(done) = self.done.take() {
+            let _ = done(Err(&Error::other("body dropped before its end")));
+        }
+    }
+}
+
+/// Returns a future that writes all chunks of `stream` to `io`, using the chunked
+/// transfer coding if `chunked` is true, and resolves to `io` once flushed.
+pub fn write<T>(io: T, stream: BodyStream, chunked: bool) -> IoFuture<T>
+where
+    T: 'static + AsyncWrite + Send,
+{
+    Box::new(stream
+        .filter(|chunk| !chunk.is_empty())
+        .fold(io, move |io, chunk| if chunked {
+            let mut buf = format!("{:x}\r\n", chunk.len()).into_bytes();
+            buf.extend_from_slice(&chunk);
+            buf.extend_from_slice(b"\r\n");
+            Either::A(write_all(io, buf).map(|(io, _)| io))
+        } else {
+            Either::B(write_all(io, chunk).map(|(io, _)| io))
+        })
+        .and_then(move |io| if chunked {
+            Either::A(write_all(io, b"0\r\n\r\n").map(|(io, _)| io))
+        } else {
+            Either::B(future::ok(io))
+        })
+        .and_then(flush))
+}
+
+/// Reads more data from `io` into `buf`, and returns the number of bytes read.
+fn read_more<R: AsyncRead>(io: &mut R, buf: &mut BytesMut) -> Poll<usize, Error> {
+    let mut chunk = [0; CHUNK_SIZE];
+    match io.poll_read(&mut chunk)? {
+        Async::Ready(len) => {
+            buf.extend_from_slice(&chunk[..len]);
+            Ok(Async::Ready(len))
+        }
+        Async::NotReady => Ok(Async::NotReady),
+    }
+}
+
+/// Returns a future that reads the head of a response to a request with the given
+/// method from `io`, and resolves to the response, without its body, and a stream of
+/// its body.
+///
+/// Interim 1xx responses are skipped, except 101 Switching Protocols. Responses to
+/// HEAD requests have an empty body, whatever their `Content-Length`.
+pub fn read_response<T>(io: T, method: &Method) -> IoFuture<(HttpResponse, BodyStream)>
+where
+    T: 'static + AsyncRead + Send,
+{
+    read_response_with(io, BytesMut::new(), *method == Method::Head)
+}
+
+/// Same as `read_response`, with data already read from `io`.
+fn read_response_with<T>(io: T, buf: BytesMut, head: bool) -> IoFuture<(HttpResponse, BodyStream)>
+where
+    T: 'static + AsyncRead + Send,
+{
+    Box::new(ReadHead {
+        io: Some(io),
+        buf: buf,
+    }.and_then(move |(res, io, buf)| {
+        let body = BodyDecoder {
+            io: io,
+            buf: buf,
+            framing: Framing::of_response(&res, head)?,
+        };
+        Ok((res, Box::new(body) as BodyStream))
+    }))
+}
+
+/// Returns a future that reads the head of a response from `io`, and resolves to
+/// the response, without its body, `io`, and the data read past the head.
+///
+/// Interim 1xx responses are skipped, except 101 Switching Protocols.
+pub fn read_head<T>(io: T) -> IoFuture<(HttpResponse, T, BytesMut)>
+where
+    T: 'static + AsyncRead + Send,
+{
+    Box::new(ReadHead {
+        io: Some(io),
+        buf: BytesMut::new(),
+    })
+}
+
+/// Returns a future that waits for the server to accept the body of a request sent
+/// with `Expect: 100-continue`, then writes the body, and resolves to the response.
+///
+/// The body is written when a 100 Continue response is received, or when `timeout`
+/// resolves, whichever comes first; it is not written if a final response is received
+/// before.
+pub fn write_after_continue<T, F>(
+    io: T,
+    stream: BodyStream,
+    chunked: bool,
+    method: &Method,
+    timeout: F,
+) -> IoFuture<(HttpResponse, BodyStream)>
+where
+    T: 'static + AsyncRead + AsyncWrite + Send,
+    F: 'static + Future<Item = (), Error = Error> + Send,
+{
+    let head = *method == Method::Head;
+    let wait = AwaitContinue {
+        io: Some(io),
+        buf: BytesMut::new(),
+        timeout: timeout,
+    };
+    Box::new(wait.and_then(move |(io, buf, proceed)| if proceed {
+        Either::A(write(io, stream, chunked).and_then(move |io| read_response_with(io, buf, head)))
+    } else {
+        Either::B(read_response_with(io, buf, head))
+    }))
+}
+
+/// Future that resolves to the connection, the data read so far, and whether the
+/// body should be sent.
+struct AwaitContinue<T, F> {
+    io: Option<T>,
+    buf: BytesMut,
+    timeout: F,
+}
+
+impl<T: AsyncRead, F: Future<Item = (), Error = Error>> Future for AwaitContinue<T, F> {
+    type Item = (T, BytesMut, bool);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<(T, BytesMut, bool), Error> {
+        loop {
+            let parsed = match parser::response(&self.buf) {
+                IResult::Done(rest, res) => Some((self.buf.len() - rest.len(), res.status())),
+                IResult::Incomplete(_) => None,
+                IResult::Error(e) => return Err(Error::new(ErrorKind::InvalidData, e)),
+            };
+
+            let proceed = match parsed {
+                Some((len, status)) if (100..200).contains(&status) && status != 101 => {
+                    self.buf.split_to(len);
+                    if status == 100 {
+                        Some(true)
+                    } else {
+                        continue;
+                    }
+                }
+                // the final response is left in the buffer
+                Some(_) => Some(false),
+                None => match self.timeout.poll()? {
+                    Async::Ready(()) => Some(true),
+                    Async::NotReady => None,
+                },
+            };
+
+            if let Some(proceed) = proceed {
+                let io = self.io.take().expect("poll after completion");
+                return Ok(Async::Ready((io, self.buf.take(), proceed)));
+            }
+
+            let io = self.io.as_mut().expect("poll after completion");
+            match read_more(io, &mut self.buf)? {
+                Async::Ready(0) => {
+                    return Err(Error::new(ErrorKind::UnexpectedEof, "connection closed before response"))
+                }
+                Async::Ready(_) => (),
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+struct ReadHead<T> {
+    io: Option<T>,
+    buf: BytesMut,
+}
+
+impl<T: AsyncRead> Future for ReadHead<T> {
+    type Item = (HttpResponse, T, BytesMut);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<(HttpResponse, T, BytesMut), Error> {
+        loop {
+            let parsed = match parser::response(&self.buf) {
+                IResult::Done(rest, res) => Some((self.buf.len() - rest.len(), res)),
+                IResult::Incomplete(_) => None,
+                IResult::Error(e) => return Err(Error::new(ErrorKind::InvalidData, e)),
+            };
+
+            if let Some((len, res)) = parsed {
+                self.buf.split_to(len);
+                if res.is_informational() && res.status() != 101 {
+                    trace!("skipped interim {} response", res.status());
+                    continue;
+                }
+                trace!("received {} response head of {} bytes", res.status(), len);
+
+                let io = self.io.take().expect("poll after completion");
+                return Ok(Async::Ready((res, io, self.buf.take())));
+            }
+
+            let io = self.io.as_mut().expect("poll after completion");
+            match read_more(io, &mut self.buf)? {
+                Async::Ready(0) => {
+                    return Err(Error::new(ErrorKind::UnexpectedEof, "connection closed before response"))
+                }
+                Async::Ready(_) => (),
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+/// Stream of the chunks of a response body.
+struct BodyDecoder<T> {
+    io: T,
+    buf: BytesMut,
+    framing: Framing,
+}
+
+impl<T: AsyncRead> Stream for BodyDecoder<T> {
+    type Item = Bytes;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Bytes>, Error> {
+        loop {
+            match self.framing.decode(&mut self.buf)? {
+                Decoded::Data(chunk) => return Ok(Async::Ready(Some(chunk))),
+                Decoded::End => return Ok(Async::Ready(None)),
+                Decoded::Incomplete => (),
+            }
+
+            // more data is needed
+            match read_more(&mut self.io, &mut self.buf)? {
+                Async::Ready(0) => self.framing.decode_eof()?,
+                Async::Ready(_) => (),
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
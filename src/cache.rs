@@ -0,0 +1,792 @@
+//! HTTP response cache (RFC 7234).
+//!
+//! A `CachingClient` acts as a private cache in front of a `Client`: responses to GET
+//! requests are stored according to their `Cache-Control`, `Expires` and `Vary`
+//! headers, served while fresh, and revalidated with `If-None-Match` and
+//! `If-Modified-Since` once stale.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, Error, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::str;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::{Future, future};
+
+use url::Url;
+
+use md5;
+
+use client::{Client, FutureResponse};
+use connect::{Connect, HttpConnector};
+use response::{self, new_header, new_response};
+use {date, HttpRequest, HttpResponse, Method};
+
+/// Upper bound of the heuristic freshness lifetime, one day.
+const MAX_HEURISTIC_LIFETIME: u64 = 86_400;
+
+/// Value to which greater delta-seconds are clamped (RFC 7234 section 1.2.1).
+const MAX_DELTA_SECONDS: u64 = 1 << 31;
+
+/// Parses a delta-seconds value, such as the one of `Age` or `max-age`.
+fn delta_seconds(value: &str) -> Option<Duration> {
+    if value.is_empty() || !value.bytes().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let seconds = value.parse().unwrap_or(MAX_DELTA_SECONDS);
+    Some(Duration::from_secs(cmp::min(seconds, MAX_DELTA_SECONDS)))
+}
+
+/// Returns the duration between `earlier` and `later`, or zero if `later` is earlier.
+fn elapsed(later: SystemTime, earlier: SystemTime) -> Duration {
+    later.duration_since(earlier).unwrap_or(Duration::from_secs(0))
+}
+
+/// Returns the values of all request headers with the given name, joined by commas.
+fn header_value(headers: &[(Cow<'static, str>, Cow<'static, str>)], name: &str) -> Option<String> {
+    let values = headers
+        .iter()
+        .filter(|&&(ref key, _)| key.eq_ignore_ascii_case(name))
+        .map(|&(_, ref value)| value.trim())
+        .collect::<Vec<_>>();
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.join(", "))
+    }
+}
+
+/// Returns the values of all response headers with the given name.
+fn response_values<'a>(res: &'a HttpResponse, name: &'a str) -> Vec<&'a str> {
+    res.headers()
+        .iter()
+        .filter(|header| header.name().eq_ignore_ascii_case(name))
+        .filter_map(|header| header.value())
+        .collect()
+}
+
+/// Cache-Control directives.
+struct Directives(Vec<(String, Option<String>)>);
+
+impl Directives {
+    fn parse<'a, I: IntoIterator<Item = &'a str>>(values: I) -> Directives {
+        let mut directives = vec![];
+        for value in values {
+            for directive in value.split(',') {
+                let mut parts = directive.splitn(2, '=');
+                let name = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+                if !name.is_empty() {
+                    let value = parts.next().map(|value| value.trim().trim_matches('"').to_string());
+                    directives.push((name, value));
+                }
+            }
+        }
+        Directives(directives)
+    }
+
+    fn of_request(req: &HttpRequest) -> Directives {
+        // Pragma: no-cache is only honored without Cache-Control (RFC 7234 section 5.4)
+        let value = header_value(&req.headers, "Cache-Control")
+            .or_else(|| header_value(&req.headers, "Pragma"));
+        Directives::parse(value.as_ref().map(|value| value.as_str()))
+    }
+
+    fn of_response(res: &HttpResponse) -> Directives {
+        Directives::parse(response_values(res, "Cache-Control"))
+    }
+
+    fn get(&self, name: &str) -> Option<Option<&str>> {
+        self.0
+            .iter()
+            .find(|&&(ref key, _)| key == name)
+            .map(|&(_, ref value)| value.as_ref().map(|value| value.as_str()))
+    }
+
+    fn has(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    fn seconds(&self, name: &str) -> Option<Duration> {
+        self.get(name).and_then(|value| value).and_then(delta_seconds)
+    }
+}
+
+/// Returns true if responses with the given status code can be stored without
+/// explicit freshness information (RFC 7231 section 6.1).
+fn is_heuristically_cacheable(status: u32) -> bool {
+    matches!(status, 200 | 203 | 204 | 300 | 301 | 404 | 405 | 410 | 414 | 501)
+}
+
+/// Response stored in a cache, along with what is needed to compute its age
+/// and to match it against later requests.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Entry {
+    response: HttpResponse,
+    request_time: SystemTime,
+    response_time: SystemTime,
+    vary: Vec<(String, Option<String>)>,
+}
+
+impl Entry {
+    fn new(
+        headers: &[(Cow<'static, str>, Cow<'static, str>)],
+        response: HttpResponse,
+        request_time: SystemTime,
+        response_time: SystemTime,
+    ) -> Entry {
+        let vary = response_values(&response, "Vary")
+            .iter()
+            .flat_map(|value| value.split(','))
+            .map(|name| name.trim().to_ascii_lowercase())
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                let value = header_value(headers, &name);
+                (name, value)
+            })
+            .collect();
+
+        Entry {
+            response: response,
+            request_time: request_time,
+            response_time: response_time,
+            vary: vary,
+        }
+    }
+
+    /// Returns the stored response.
+    pub fn response(&self) -> &HttpResponse {
+        &self.response
+    }
+
+    /// Returns the time at which the request was sent.
+    pub fn request_time(&self) -> SystemTime {
+        self.request_time
+    }
+
+    /// Returns the time at which the response was received.
+    pub fn response_time(&self) -> SystemTime {
+        self.response_time
+    }
+
+    /// Returns true if the request headers selected by `Vary` match those of `req`.
+    fn matches(&self, req: &HttpRequest) -> bool {
+        self.vary.iter().all(|&(ref name, ref value)| {
+            name != "*" && header_value(&req.headers, name) == *value
+        })
+    }
+
+    /// Returns true if this response may be stored.
+    fn is_storable(&self) -> bool {
+        let status = self.response.status();
+        let directives = Directives::of_response(&self.response);
+        if self.response.is_informational() || status == 206 || status == 304 ||
+            directives.has("no-store") || self.vary.iter().any(|&(ref name, _)| name == "*")
+        {
+            return false;
+        }
+
+        let explicit = directives.has("max-age") || directives.has("public") ||
+            self.response["Expires"].is_some();
+        let validator = self.response["ETag"].is_some() || self.response["Last-Modified"].is_some();
+        (explicit || is_heuristically_cacheable(status)) &&
+            (validator || self.freshness_lifetime() > Duration::from_secs(0))
+    }
+
+    /// Returns the value of the `Date` header, or else the time of the response.
+    fn date(&self) -> SystemTime {
+        self.response.date().unwrap_or(self.response_time)
+    }
+
+    /// Returns the freshness lifetime of the response (RFC 7234 section 4.2.1).
+    ///
+    /// Without `max-age` or `Expires`, the lifetime is 10% of the time since the
+    /// response was last modified, up to one day.
+    fn freshness_lifetime(&self) -> Duration {
+        let directives = Directives::of_response(&self.response);
+        if let Some(max_age) = directives.seconds("max-age") {
+            return max_age;
+        }
+
+        if let Some(ref expires) = self.response["Expires"] {
+            // an invalid date represents a time in the past
+            return date::parse(expires).map_or(Duration::from_secs(0), |expires| {
+                elapsed(expires, self.date())
+            });
+        }
+
+        match self.response.last_modified() {
+            Some(last_modified) if is_heuristically_cacheable(self.response.status()) => {
+                cmp::min(
+                    elapsed(self.date(), last_modified) / 10,
+                    Duration::from_secs(MAX_HEURISTIC_LIFETIME),
+                )
+            }
+            _ => Duration::from_secs(0),
+        }
+    }
+
+    /// Returns the current age of the response (RFC 7234 section 4.2.3).
+    fn age(&self, now: SystemTime) -> Duration {
+        let apparent_age = elapsed(self.response_time, self.date());
+        let age_value = self.response["Age"]
+            .as_ref()
+            .and_then(|age| delta_seconds(age))
+            .unwrap_or(Duration::from_secs(0));
+        let response_delay = elapsed(self.response_time, self.request_time);
+        let corrected_initial_age = cmp::max(apparent_age, age_value.saturating_add(response_delay));
+        corrected_initial_age.saturating_add(elapsed(now, self.response_time))
+    }
+
+    /// Returns true if the response can be used to satisfy a request with the given
+    /// directives without validation (RFC 7234 section 4).
+    fn satisfies(&self, request: &Directives, now: SystemTime) -> bool {
+        let response = Directives::of_response(&self.response);
+        if response.has("no-cache") || request.has("no-cache") {
+            return false;
+        }
+
+        let age = self.age(now);
+        let lifetime = self.freshness_lifetime();
+        if request.seconds("max-age").map_or(false, |max_age| age > max_age) {
+            return false;
+        }
+        if request.seconds("min-fresh").map_or(false, |min_fresh| age.saturating_add(min_fresh) > lifetime) {
+            return false;
+        }
+        if age < lifetime {
+            return true;
+        }
+
+        // stale responses are only served if the client accepts it
+        if response.has("must-revalidate") {
+            return false;
+        }
+        match request.get("max-stale") {
+            Some(None) => true,
+            Some(Some(_)) => request.seconds("max-stale").map_or(false, |max_stale| {
+                age - lifetime <= max_stale
+            }),
+            None => false,
+        }
+    }
+
+    /// Updates the stored response with the headers of a 304 Not Modified response
+    /// (RFC 7234 section 4.3.4).
+    fn update(mut self, res: &HttpResponse, request_time: SystemTime, response_time: SystemTime) -> Entry {
+        {
+            let is_framing = |name: &str| {
+                name.eq_ignore_ascii_case("Content-Length") || name.eq_ignore_ascii_case("Transfer-Encoding")
+            };
+            let headers = response::headers_mut(&mut self.response);
+            for header in res.headers() {
+                headers.retain(|stored| !stored.name().eq_ignore_ascii_case(header.name()) || is_framing(header.name()));
+            }
+            headers.extend(res.headers().iter().filter(|header| !is_framing(header.name())).cloned());
+        }
+        self.request_time = request_time;
+        self.response_time = response_time;
+        self
+    }
+
+    /// Returns a copy of the stored response with its current `Age`.
+    fn response_at(&self, now: SystemTime) -> HttpResponse {
+        let mut res = self.response.clone();
+        {
+            let headers = response::headers_mut(&mut res);
+            headers.retain(|header| !header.name().eq_ignore_ascii_case("Age"));
+            headers.push(new_header("Age", self.age(now).as_secs().to_string()));
+        }
+        res
+    }
+
+    /// Serializes this entry, e.g. to store it in a file.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let secs = |time: SystemTime| elapsed(time, UNIX_EPOCH).as_secs();
+        let (major, minor) = self.response.version();
+        let mut head = format!(
+            "{} {}\r\n{}.{} {}\r\n",
+            secs(self.request_time),
+            secs(self.response_time),
+            major,
+            minor,
+            self.response.status()
+        );
+        for &(ref name, ref value) in &self.vary {
+            match *value {
+                Some(ref value) => head.push_str(&format!("V {}: {}\r\n", name, value)),
+                None => head.push_str(&format!("V {}\r\n", name)),
+            }
+        }
+        for header in self.response.headers() {
+            head.push_str(&format!("H {}: {}\r\n", header.name(), header.value().unwrap_or("")));
+        }
+        head.push_str("\r\n");
+
+        let mut bytes = head.into_bytes();
+        bytes.extend_from_slice(self.response.get_body());
+        bytes
+    }
+
+    /// Deserializes an entry serialized by `to_bytes`.
+    ///
+    /// Times are only stored with a precision of one second.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Entry> {
+        let end = bytes.windows(4).position(|window| window == b"\r\n\r\n")?;
+        let head = str::from_utf8(&bytes[..end]).ok()?;
+        let mut lines = head.split("\r\n");
+
+        let mut times = lines.next()?.split(' ').map(|secs| {
+            secs.parse().ok().map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+        });
+        let request_time = times.next()??;
+        let response_time = times.next()??;
+
+        let mut status_line = lines.next()?.split(' ');
+        let mut version = status_line.next()?.split('.').map(|n| n.parse().ok());
+        let version = (version.next()??, version.next()??);
+        let status = status_line.next()?.parse().ok()?;
+
+        let mut vary = vec![];
+        let mut headers = vec![];
+        for line in lines {
+            if line.len() < 2 {
+                return None;
+            }
+            let mut parts = line[2..].splitn(2, ": ");
+            let name = parts.next()?;
+            let value = parts.next();
+            match &line[..2] {
+                "V " => vary.push((name.to_string(), value.map(|value| value.to_string()))),
+                "H " => headers.push(new_header(name, value.unwrap_or(""))),
+                _ => return None,
+            }
+        }
+
+        let mut res = new_response(version, status, headers);
+        response::append(&mut res, &bytes[end + 4..]);
+        Some(Entry {
+            response: res,
+            request_time: request_time,
+            response_time: response_time,
+            vary: vary,
+        })
+    }
+}
+
+/// Storage of cache entries, keyed by URL.
+///
+/// Storage errors are not reported: the cache behaves as if the entry was missing.
+pub trait Storage {
+    /// Returns the entry stored under the given key.
+    fn get(&self, key: &str) -> Option<Entry>;
+
+    /// Stores an entry under the given key, replacing any previous one.
+    fn put(&self, key: &str, entry: Entry);
+
+    /// Removes the entry stored under the given key.
+    fn remove(&self, key: &str);
+}
+
+/// In-memory storage that evicts the least recently used entries.
+pub struct MemoryStorage {
+    capacity: usize,
+    entries: RefCell<HashMap<String, Entry>>,
+    /// Keys from the least to the most recently used.
+    recent: RefCell<VecDeque<String>>,
+}
+
+impl MemoryStorage {
+    /// Creates a new storage that holds up to `capacity` entries.
+    pub fn new(capacity: usize) -> MemoryStorage {
+        MemoryStorage {
+            capacity: capacity,
+            entries: RefCell::new(HashMap::new()),
+            recent: RefCell::new(VecDeque::new()),
+        }
+    }
+
+    /// Returns the number of stored entries.
+    pub fn len(&self) -> usize {
+        self.entries.borrow().len()
+    }
+
+    /// Returns true if no entries are stored.
+    pub fn is_empty(&self) -> bool {
+        self.entries.borrow().is_empty()
+    }
+
+    fn touch(&self, key: &str) {
+        let mut recent = self.recent.borrow_mut();
+        recent.retain(|recent| recent != key);
+        recent.push_back(key.to_string());
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn get(&self, key: &str) -> Option<Entry> {
+        let entry = self.entries.borrow().get(key).cloned();
+        if entry.is_some() {
+            self.touch(key);
+        }
+        entry
+    }
+
+    fn put(&self, key: &str, entry: Entry) {
+        self.entries.borrow_mut().insert(key.to_string(), entry);
+        self.touch(key);
+
+        let mut entries = self.entries.borrow_mut();
+        let mut recent = self.recent.borrow_mut();
+        while entries.len() > self.capacity {
+            match recent.pop_front() {
+                Some(oldest) => entries.remove(&oldest),
+                None => break,
+            };
+        }
+    }
+
+    fn remove(&self, key: &str) {
+        self.entries.borrow_mut().remove(key);
+        self.recent.borrow_mut().retain(|recent| recent != key);
+    }
+}
+
+/// Storage that keeps each entry in a file of a directory.
+pub struct DiskStorage {
+    dir: PathBuf,
+}
+
+impl DiskStorage {
+    /// Creates a new storage in the given directory, which is created if needed.
+    pub fn new<P: AsRef<Path>>(dir: P) -> io::Result<DiskStorage> {
+        fs::create_dir_all(&dir)?;
+        Ok(DiskStorage { dir: dir.as_ref().to_path_buf() })
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{:x}", md5::compute(key)))
+    }
+}
+
+impl Storage for DiskStorage {
+    fn get(&self, key: &str) -> Option<Entry> {
+        let mut bytes = vec![];
+        File::open(self.path(key))
+            .and_then(|mut file| file.read_to_end(&mut bytes))
+            .ok()?;
+        Entry::from_bytes(&bytes)
+    }
+
+    fn put(&self, key: &str, entry: Entry) {
+        // write to a temporary file first so that readers never see a partial entry
+        let path = self.path(key);
+        let tmp = path.with_extension("tmp");
+        let _ = fs::write(&tmp, entry.to_bytes()).and_then(|_| fs::rename(&tmp, &path));
+    }
+
+    fn remove(&self, key: &str) {
+        let _ = fs::remove_file(self.path(key));
+    }
+}
+
+/// Returns the cache key of the given URL.
+fn key(url: &Url) -> String {
+    let mut url = url.clone();
+    url.set_fragment(None);
+    url.into_string()
+}
+
+/// HTTP client that acts as a private cache in front of a `Client`.
+pub struct CachingClient<C = HttpConnector, S = MemoryStorage> {
+    client: Client<C>,
+    storage: Rc<S>,
+}
+
+impl<C, S> Clone for CachingClient<C, S> {
+    fn clone(&self) -> CachingClient<C, S> {
+        CachingClient {
+            client: self.client.clone(),
+            storage: self.storage.clone(),
+        }
+    }
+}
+
+impl<C: Connect + 'static, S: Storage + 'static> CachingClient<C, S> {
+    /// Creates a new caching client that sends requests with `client` and stores
+    /// responses in `storage`.
+    pub fn new(client: Client<C>, storage: S) -> CachingClient<C, S> {
+        CachingClient {
+            client: client,
+            storage: Rc::new(storage),
+        }
+    }
+
+    /// Returns the client used to send requests.
+    pub fn client(&self) -> &Client<C> {
+        &self.client
+    }
+
+    /// Returns the storage of this cache.
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    /// Returns a future that resolves to the response to a GET request to the given URL.
+    pub fn get<U: AsRef<str>>(&self, url: U) -> FutureResponse {
+        match HttpRequest::get(url) {
+            Ok(req) => self.request(req),
+            Err(e) => Box::new(future::err(Error::new(ErrorKind::InvalidInput, e))),
+        }
+    }
+
+    /// Returns a future that resolves to the response to the given request.
+    ///
+    /// Only responses to GET requests are stored. A stored response is returned
+    /// with its current `Age` if it is fresh enough, otherwise it is revalidated and
+    /// a 304 Not Modified response is merged into it. Successful responses to unsafe
+    /// methods (e.g. POST) invalidate the response stored for the same URL.
+    pub fn request(&self, req: HttpRequest) -> FutureResponse {
+        let key = key(&req.url);
+        match req.method {
+            Method::Get => (),
+            Method::Head | Method::Options | Method::Trace | Method::Connect => {
+                return self.client.request(req)
+            }
+            _ => {
+                let storage = self.storage.clone();
+                return Box::new(self.client.request(req).map(move |res| {
+                    if res.is_successful() || res.is_redirection() {
+                        storage.remove(&key);
+                    }
+                    res
+                }));
+            }
+        }
+
+        let directives = Directives::of_request(&req);
+        if directives.has("no-store") {
+            return self.client.request(req);
+        }
+
+        let request_time = SystemTime::now();
+        let stored = self.storage.get(&key).filter(|entry| entry.matches(&req));
+        let headers = req.headers.clone();
+        let req = match stored {
+            Some(ref entry) if entry.satisfies(&directives, request_time) => {
+                return Box::new(future::ok(entry.response_at(request_time)));
+            }
+            Some(ref entry) => req.conditional_on(&entry.response),
+            None => req,
+        };
+
+        let storage = self.storage.clone();
+        Box::new(self.client.request(req).map(move |res| {
+            let response_time = SystemTime::now();
+            match stored {
+                Some(entry) if res.status() == 304 => {
+                    let entry = entry.update(&res, request_time, response_time);
+                    let res = entry.response_at(response_time);
+                    storage.put(&key, entry);
+                    res
+                }
+                _ => {
+                    let entry = Entry::new(&headers, res, request_time, response_time);
+                    if entry.is_storable() {
+                        storage.put(&key, entry.clone());
+                    }
+                    entry.response
+                }
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use futures::Future;
+
+    use {Client, HttpRequest, Method, date};
+    use mock::{MockConnector, TempPath};
+    use response::{new_header, new_response};
+    use super::{CachingClient, Directives, DiskStorage, Entry, MemoryStorage, Storage};
+
+    fn requests(output: &Arc<Mutex<Vec<u8>>>) -> Vec<String> {
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        output.split("GET ").skip(1).map(|req| req.to_string()).collect()
+    }
+
+    #[test]
+    fn fresh() {
+        let output = Arc::new(Mutex::new(vec![]));
+        let client = CachingClient::new(
+            Client::with_connector(MockConnector::new(
+                vec![
+                    b"HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\nContent-Length: 2\r\n\r\nv1",
+                    b"HTTP/1.1 200 OK\r\nCache-Control: no-store\r\nContent-Length: 2\r\n\r\nv2",
+                ],
+                &output,
+            )),
+            MemoryStorage::new(10),
+        );
+
+        let res = client.get("http://example.com/config").wait().unwrap();
+        assert_eq!(res.get_body(), b"v1");
+        let res = client.get("http://example.com/config#section").wait().unwrap();
+        assert_eq!(res.get_body(), b"v1");
+        assert!(res.is("Age", "0"));
+        assert_eq!(requests(&output).len(), 1);
+
+        let req = HttpRequest::get("http://example.com/config").unwrap().header("Cache-Control", "no-cache");
+        let res = client.request(req).wait().unwrap();
+        assert_eq!(res.get_body(), b"v2");
+        assert_eq!(requests(&output).len(), 2);
+    }
+
+    #[test]
+    fn revalidate() {
+        let output = Arc::new(Mutex::new(vec![]));
+        let client = CachingClient::new(
+            Client::with_connector(MockConnector::new(
+                vec![
+                    b"HTTP/1.1 200 OK\r\nCache-Control: no-cache\r\nETag: \"v1\"\r\n\
+                      Last-Modified: Sun, 06 Nov 1994 08:49:37 GMT\r\nX-Version: 1\r\n\
+                      Content-Length: 5\r\n\r\nhello",
+                    b"HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\nX-Version: 2\r\n\r\n",
+                ],
+                &output,
+            )),
+            MemoryStorage::new(10),
+        );
+
+        client.get("http://example.com/config").wait().unwrap();
+        let res = client.get("http://example.com/config").wait().unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.get_body(), b"hello");
+        assert!(res.is("X-Version", "2"));
+        assert!(res.is("Content-Length", "5"));
+
+        let requests = requests(&output);
+        assert_eq!(requests.len(), 2);
+        assert!(!requests[0].contains("If-None-Match"));
+        assert!(requests[1].contains("If-None-Match: \"v1\"\r\n"));
+        assert!(requests[1].contains("If-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n"));
+        assert!(client.storage().get("http://example.com/config").unwrap().response().is("X-Version", "2"));
+    }
+
+    #[test]
+    fn vary_and_invalidation() {
+        let output = Arc::new(Mutex::new(vec![]));
+        let client = CachingClient::new(
+            Client::with_connector(MockConnector::new(
+                vec![
+                    b"HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\nVary: Accept-Language\r\n\
+                      Content-Length: 7\r\n\r\nbonjour",
+                    b"HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\nVary: Accept-Language\r\n\
+                      Content-Length: 5\r\n\r\nhello",
+                    b"HTTP/1.1 204 No Content\r\nServer: mock\r\n\r\n",
+                ],
+                &output,
+            )),
+            MemoryStorage::new(10),
+        );
+
+        let get = |lang: &'static str| {
+            let req = HttpRequest::get("http://example.com/greeting").unwrap().header("Accept-Language", lang);
+            client.request(req).wait().unwrap()
+        };
+        assert_eq!(get("fr").get_body(), b"bonjour");
+        assert_eq!(get("fr").get_body(), b"bonjour");
+        assert_eq!(get("en").get_body(), b"hello");
+        assert_eq!(get("en").get_body(), b"hello");
+        assert_eq!(client.storage().len(), 1);
+
+        let req = HttpRequest::new(Method::Delete, "http://example.com/greeting").unwrap();
+        assert_eq!(client.request(req).wait().unwrap().status(), 204);
+        assert!(client.storage().is_empty());
+    }
+
+    #[test]
+    fn freshness() {
+        let date = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let entry = |headers: Vec<(&str, String)>| {
+            let headers = headers.into_iter().map(|(name, value)| new_header(name, value)).collect();
+            Entry::new(&[], new_response((1, 1), 200, headers), date, date)
+        };
+
+        let expires = entry(vec![
+            ("Date", date::format(date)),
+            ("Expires", date::format(date + Duration::from_secs(300))),
+        ]);
+        assert_eq!(expires.freshness_lifetime(), Duration::from_secs(300));
+        assert!(expires.is_storable());
+
+        let invalid = entry(vec![("Expires", "0".to_string()), ("Cache-Control", "max-age=10".to_string())]);
+        assert_eq!(invalid.freshness_lifetime(), Duration::from_secs(10));
+
+        let heuristic = entry(vec![
+            ("Date", date::format(date)),
+            ("Last-Modified", date::format(date - Duration::from_secs(1000))),
+        ]);
+        assert_eq!(heuristic.freshness_lifetime(), Duration::from_secs(100));
+        assert!(heuristic.is_storable());
+
+        let aged = entry(vec![("Age", "30".to_string())]);
+        assert_eq!(aged.age(date + Duration::from_secs(5)), Duration::from_secs(35));
+        assert!(!aged.is_storable());
+
+        // ages too large to be represented are clamped
+        let old = entry(vec![("Age", "18446744073709551615".to_string()), ("Cache-Control", "max-age=10".to_string())]);
+        assert_eq!(old.age(date), Duration::from_secs(1 << 31));
+        assert!(!old.satisfies(&Directives::parse(Some("min-fresh=1")), date));
+        let huge = entry(vec![("Age", "99999999999999999999999".to_string())]);
+        assert_eq!(huge.age(date + Duration::from_secs(5)), Duration::from_secs((1 << 31) + 5));
+    }
+
+    #[test]
+    fn memory_storage() {
+        let entry = Entry::new(&[], new_response((1, 1), 200, vec![]), UNIX_EPOCH, UNIX_EPOCH);
+        let storage = MemoryStorage::new(2);
+        storage.put("a", entry.clone());
+        storage.put("b", entry.clone());
+        assert!(storage.get("a").is_some());
+        storage.put("c", entry.clone());
+        assert!(storage.get("a").is_some());
+        assert!(storage.get("b").is_none());
+        assert!(storage.get("c").is_some());
+        storage.remove("a");
+        assert_eq!(storage.len(), 1);
+    }
+
+    #[test]
+    fn disk_storage() {
+        let dir = TempPath::new("cache");
+        let storage = DiskStorage::new(&*dir).unwrap();
+
+        let time = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+        let mut res = new_response((1, 0), 200, vec![
+            new_header("Vary", "Accept, Accept-Language"),
+            new_header("ETag", "\"x\""),
+        ]);
+        ::response::append(&mut res, b"\r\n\r\nbody\x00");
+        let headers = vec![("Accept".into(), "text/html".into())];
+        let entry = Entry::new(&headers, res, time, time + Duration::from_secs(1));
+        assert_eq!(entry.vary, vec![
+            ("accept".to_string(), Some("text/html".to_string())),
+            ("accept-language".to_string(), None),
+        ]);
+
+        storage.put("http://example.com/", entry.clone());
+        assert_eq!(storage.get("http://example.com/"), Some(entry));
+        assert_eq!(storage.get("http://example.com/other"), None);
+        storage.remove("http://example.com/");
+        assert_eq!(storage.get("http://example.com/"), None);
+    }
+}
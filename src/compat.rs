@@ -0,0 +1,91 @@
+//! Interoperability of the futures 0.1 API with `std::future` and tokio 1.x (legacy and
+//! tokio1 features).
+//!
+//! This module adapts tokio 1.x connections to the futures 0.1 `Client`, and its futures
+//! to `std::future::Future`, so that its features can be used on a tokio 1.x runtime:
+//!
+//! ```no-run
+//! let client = Client::with_connector(TokioConnector).continue_timeout(Duration::from_secs(2));
+//! let res = compat::std_future(client.get("http://example.com/")).await?;
+//! ```
+
+use std::io::{Error, ErrorKind};
+
+use futures::{Future, future};
+
+use futures03::TryFutureExt;
+use futures03::compat::{Compat, Compat01As03};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+use tokio_io::IoFuture;
+
+use tokio_util::compat::{Compat as TokioCompat, TokioAsyncReadCompatExt};
+
+use url::Url;
+
+use connect::Connect;
+
+/// Tokio 1.x connection, adapted to the I/O traits used by this crate.
+pub type Connection<T> = Compat<TokioCompat<T>>;
+
+/// Adapts a tokio 1.x connection to the I/O traits used by this crate.
+pub fn connection<T: AsyncRead + AsyncWrite + Unpin>(io: T) -> Connection<T> {
+    Compat::new(io.compat())
+}
+
+/// Converts a future of this crate, such as the ones returned by a `Client`, to a
+/// `std::future::Future`.
+pub fn std_future<F: Future>(future: F) -> Compat01As03<F> {
+    Compat01As03::new(future)
+}
+
+/// Connector that opens TCP connections with tokio 1.x.
+///
+/// The futures of a client using this connector must be run on a tokio 1.x runtime.
+#[derive(Clone, Copy, Debug)]
+pub struct TokioConnector;
+
+impl Connect for TokioConnector {
+    type Io = Connection<TcpStream>;
+
+    fn connect(&self, url: &Url) -> IoFuture<Self::Io> {
+        let addr = match (url.host_str(), url.port_or_known_default()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            _ => return Box::new(future::err(Error::new(ErrorKind::InvalidInput, "no address"))),
+        };
+        Box::new(Compat::new(Box::pin(TcpStream::connect(addr).map_ok(connection))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use tokio::runtime::Builder;
+
+    use Client;
+    use super::{std_future, TokioConnector};
+
+    #[test]
+    fn tokio1() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0; 1024];
+            let len = stream.read(&mut buf).unwrap();
+            assert!(buf[..len].starts_with(b"GET /tokio HTTP/1.1\r\n"));
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello").unwrap();
+        });
+
+        let runtime = Builder::new_current_thread().enable_io().build().unwrap();
+        let client = Client::with_connector(TokioConnector);
+        let res = runtime.block_on(std_future(client.get(format!("http://{}/tokio", addr)))).unwrap();
+        assert_eq!(res.get_body(), b"hello");
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,216 @@
+//! Validators used in conditional requests (RFC 7232).
+
+use std::fmt;
+use std::time::SystemTime;
+
+/// Entity tag, as found in `ETag` headers.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ETag {
+    weak: bool,
+    tag: String,
+}
+
+impl ETag {
+    /// Creates a strong entity tag; `tag` is the opaque value, without quotes.
+    pub fn strong<T: Into<String>>(tag: T) -> ETag {
+        ETag {
+            weak: false,
+            tag: tag.into(),
+        }
+    }
+
+    /// Creates a weak entity tag; `tag` is the opaque value, without quotes.
+    pub fn weak<T: Into<String>>(tag: T) -> ETag {
+        ETag {
+            weak: true,
+            tag: tag.into(),
+        }
+    }
+
+    /// Parses an entity tag such as `"xyzzy"` or `W/"xyzzy"`.
+    pub fn parse(value: &str) -> Option<ETag> {
+        match parse_one(value.trim()) {
+            Some((etag, "")) => Some(etag),
+            _ => None,
+        }
+    }
+
+    /// Parses a comma-separated list of entity tags, as found in `If-Match`
+    /// and `If-None-Match` headers.
+    ///
+    /// Returns `None` if the list is malformed.
+    pub fn parse_list(value: &str) -> Option<Vec<ETag>> {
+        let mut etags = vec![];
+        let mut rest = value;
+        loop {
+            rest = rest.trim_start_matches([' ', '\t', ',']);
+            if rest.is_empty() {
+                return Some(etags);
+            }
+            let (etag, next) = parse_one(rest)?;
+            etags.push(etag);
+            rest = next;
+        }
+    }
+
+    /// Returns true if this entity tag is weak.
+    pub fn is_weak(&self) -> bool {
+        self.weak
+    }
+
+    /// Returns the opaque value of this entity tag, without quotes.
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    /// Strong comparison: both entity tags are strong and their values are equal.
+    pub fn strong_eq(&self, other: &ETag) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+
+    /// Weak comparison: the values of both entity tags are equal.
+    pub fn weak_eq(&self, other: &ETag) -> bool {
+        self.tag == other.tag
+    }
+}
+
+/// Parses an entity tag at the beginning of `value`, and returns it along with the rest.
+fn parse_one(value: &str) -> Option<(ETag, &str)> {
+    let (weak, value) = match value.strip_prefix("W/") {
+        Some(value) => (true, value),
+        None => (false, value),
+    };
+    if !value.starts_with('"') {
+        return None;
+    }
+    let end = value[1..].find('"')? + 1;
+    let etag = ETag {
+        weak: weak,
+        tag: value[1..end].to_string(),
+    };
+    Some((etag, &value[end + 1..]))
+}
+
+impl fmt::Display for ETag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.weak {
+            write!(f, "W/")?;
+        }
+        write!(f, "\"{}\"", self.tag)
+    }
+}
+
+/// Validator of an `If-Range` header, either an entity tag or a date.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum IfRange {
+    ETag(ETag),
+    Date(SystemTime),
+}
+
+impl IfRange {
+    /// Parses the value of an `If-Range` header, either an entity tag or an HTTP-date.
+    pub fn parse(value: &str) -> Option<IfRange> {
+        ETag::parse(value)
+            .map(IfRange::ETag)
+            .or_else(|| ::date::parse(value).map(IfRange::Date))
+    }
+}
+
+impl From<ETag> for IfRange {
+    fn from(etag: ETag) -> IfRange {
+        IfRange::ETag(etag)
+    }
+}
+
+impl From<SystemTime> for IfRange {
+    fn from(date: SystemTime) -> IfRange {
+        IfRange::Date(date)
+    }
+}
+
+impl fmt::Display for IfRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            IfRange::ETag(ref etag) => write!(f, "{}", etag),
+            IfRange::Date(date) => write!(f, "{}", ::date::format(date)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use {HttpRequest, Method};
+    use response::{new_header, new_response};
+    use super::{ETag, IfRange};
+
+    #[test]
+    fn etags() {
+        let etag = ETag::parse(" W/\"a, b\" ").unwrap();
+        assert!(etag.is_weak());
+        assert_eq!(etag.tag(), "a, b");
+        assert_eq!(etag.to_string(), "W/\"a, b\"");
+        assert!(etag.weak_eq(&ETag::strong("a, b")));
+        assert!(!etag.strong_eq(&ETag::strong("a, b")));
+        assert!(ETag::strong("x").strong_eq(&ETag::parse("\"x\"").unwrap()));
+
+        assert_eq!(ETag::parse("xyzzy"), None);
+        assert_eq!(ETag::parse("\"x\"y"), None);
+        assert_eq!(
+            ETag::parse_list("\"x\", W/\"y\",\"\""),
+            Some(vec![ETag::strong("x"), ETag::weak("y"), ETag::strong("")])
+        );
+        assert_eq!(ETag::parse_list("\"x\", *"), None);
+
+        assert_eq!(IfRange::parse("W/\"x\""), Some(IfRange::ETag(ETag::weak("x"))));
+        assert!(IfRange::parse("Sun, 06 Nov 1994 08:49:37 GMT").is_some());
+        assert_eq!(IfRange::parse("x"), None);
+    }
+
+    #[test]
+    fn conditional_on() {
+        let res = new_response((1, 1), 200, vec![
+            new_header("ETag", "W/\"v1\""),
+            new_header("Last-Modified", "Sunday, 06-Nov-94 08:49:37 GMT"),
+        ]);
+
+        let req = HttpRequest::get("http://example.com/").unwrap().conditional_on(&res);
+        assert_eq!(
+            req.to_string(),
+            "GET / HTTP/1.1\r\nHost: example.com\r\nIf-None-Match: W/\"v1\"\r\n\
+             If-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n\r\n"
+        );
+
+        // weak entity tags never match If-Match
+        let req = HttpRequest::new(Method::Put, "http://example.com/").unwrap().conditional_on(&res);
+        assert_eq!(
+            req.to_string(),
+            "PUT / HTTP/1.1\r\nHost: example.com\r\n\
+             If-Unmodified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n\r\n"
+        );
+
+        // unquoted entity tags are sent back as they were received
+        let res = new_response((1, 1), 200, vec![new_header("ETag", "v2")]);
+        let req = HttpRequest::get("http://example.com/").unwrap().conditional_on(&res);
+        assert_eq!(req.to_string(), "GET / HTTP/1.1\r\nHost: example.com\r\nIf-None-Match: v2\r\n\r\n");
+    }
+
+    #[test]
+    fn setters() {
+        let date = UNIX_EPOCH + Duration::from_secs(784_111_777);
+        let req = HttpRequest::get("http://example.com/")
+            .unwrap()
+            .if_match(&[ETag::strong("a"), ETag::strong("b")])
+            .if_none_match_any()
+            .if_unmodified_since(date)
+            .if_range(ETag::strong("a"))
+            .if_range(IfRange::Date(date));
+        assert_eq!(
+            req.to_string(),
+            "GET / HTTP/1.1\r\nHost: example.com\r\nIf-Match: \"a\", \"b\"\r\n\
+             If-None-Match: *\r\nIf-Unmodified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n\
+             If-Range: Sun, 06 Nov 1994 08:49:37 GMT\r\n\r\n"
+        );
+    }
+}
@@ -0,0 +1,438 @@
+//! Connectors establish the connections over which requests are sent.
+
+use std::io::{Error, ErrorKind};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
+use std::thread;
+use std::vec;
+
+use futures::{Future, future};
+use futures::sync::oneshot;
+
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::{Handle, Remote};
+
+use tokio_io::{IoFuture, AsyncRead, AsyncWrite};
+
+use url::Url;
+use url::percent_encoding::percent_decode;
+
+use metrics::Recorder;
+use socks::Socks5;
+
+/// A connector opens a connection suitable for sending requests to a given URL.
+pub trait Connect {
+    /// Type of the connection.
+    type Io: 'static + AsyncRead + AsyncWrite + Send;
+
+    /// Returns a future that resolves to a connection to the host of the given `url`.
+    fn connect(&self, url: &Url) -> IoFuture<Self::Io>;
+
+    /// Same as `connect`, and marks the phases of the connection in `recorder`.
+    ///
+    /// The default implementation only marks the connection as established.
+    fn connect_timed(&self, url: &Url, recorder: &Recorder) -> IoFuture<Self::Io> {
+        let recorder = recorder.clone();
+        Box::new(self.connect(url).map(move |io| {
+            recorder.connected();
+            io
+        }))
+    }
+
+    /// Returns the protocol selected by the server with ALPN on `io`, e.g. `h2`.
+    ///
+    /// The default implementation returns `None`, for connectors that do not negotiate it.
+    fn negotiated_protocol(&self, _io: &Self::Io) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// Connector that opens plain TCP connections.
+#[derive(Clone)]
+pub struct HttpConnector {
+    handle: Handle,
+}
+
+impl HttpConnector {
+    /// Creates a new TCP connector using the given reactor handle.
+    pub fn new(handle: &Handle) -> HttpConnector {
+        HttpConnector { handle: handle.clone() }
+    }
+}
+
+impl Connect for HttpConnector {
+    type Io = TcpStream;
+
+    fn connect(&self, url: &Url) -> IoFuture<TcpStream> {
+        self.connect_timed(url, &Recorder::new())
+    }
+
+    fn connect_timed(&self, url: &Url, recorder: &Recorder) -> IoFuture<TcpStream> {
+        let remote = self.handle.remote().clone();
+        let recorder = recorder.clone();
+        Box::new(resolve(url).and_then(move |addrs| {
+            recorder.dns_resolved();
+            let no_address = Error::new(ErrorKind::UnexpectedEof, "no address");
+            connect_any(addrs.into_iter(), remote, no_address).map(move |io| {
+                recorder.connected();
+                io
+            })
+        }))
+    }
+}
+
+/// Resolves the host and port of the given URL on a separate thread, since the lookup
+/// blocks and would otherwise stall the reactor.
+fn resolve(url: &Url) -> IoFuture<Vec<SocketAddr>> {
+    let url = url.clone();
+    let (sender, receiver) = oneshot::channel();
+    thread::spawn(move || {
+        let _ = sender.send(url.to_socket_addrs().map(|addrs| addrs.collect()));
+    });
+    Box::new(receiver.map_err(|_| Error::other("resolver thread panicked")).and_then(|addrs| addrs))
+}
+
+/// Connects to each of the given addresses in turn, until a connection succeeds, and
+/// fails with the error of the last attempt, or `last` if there is no address.
+///
+/// The connections are opened on the reactor of `remote`, on which the future must run.
+fn connect_any(mut addrs: vec::IntoIter<SocketAddr>, remote: Remote, last: Error) -> IoFuture<TcpStream> {
+    Box::new(future::lazy(move || -> IoFuture<TcpStream> {
+        let (addr, handle) = match (addrs.next(), remote.handle()) {
+            (Some(addr), Some(handle)) => (addr, handle),
+            (Some(_), None) => return Box::new(future::err(Error::other("not running on the reactor"))),
+            (None, _) => return Box::new(future::err(last)),
+        };
+        debug!("connecting to {}", addr);
+        Box::new(TcpStream::connect(&addr, &handle).then(move |result| -> IoFuture<TcpStream> {
+            match result {
+                Ok(io) => {
+                    trace!("connected to {}", addr);
+                    Box::new(future::ok(io))
+                }
+                Err(e) => {
+                    debug!("connection to {} failed: {}", addr, e);
+                    connect_any(addrs, remote, e)
+                }
+            }
+        }))
+    }))
+}
+
+/// Connector that opens connections through a SOCKS5 proxy.
+#[derive(Clone)]
+pub struct Socks5Connector {
+    proxy: Socks5,
+    handle: Handle,
+}
+
+impl Socks5Connector {
+    /// Creates a new connector using the given proxy and reactor handle.
+    pub fn new(proxy: Socks5, handle: &Handle) -> Socks5Connector {
+        Socks5Connector {
+            proxy: proxy,
+            handle: handle.clone(),
+        }
+    }
+}
+
+impl Connect for Socks5Connector {
+    type Io = TcpStream;
+
+    fn connect(&self, url: &Url) -> IoFuture<TcpStream> {
+        self.proxy.connect(url, &self.handle)
+    }
+}
+
+/// Returns true if the given URL designates a Unix domain socket.
+///
+/// Such URLs have a `unix` or `http+unix` scheme, and their host is the
+/// percent-encoded path of the socket, e.g.
+/// `http+unix://%2Fvar%2Frun%2Fdocker.sock/containers/json`.
+pub fn is_unix(url: &Url) -> bool {
+    ::is_unix(url)
+}
+
+/// Returns the path of the Unix domain socket designated by the given URL.
+///
+/// Returns `None` for URLs without host, such as `unix:///var/run/docker.sock`, which
+/// `HttpRequest::new` rejects.
+pub fn socket_path(url: &Url) -> Option<PathBuf> {
+    if !is_unix(url) {
+        return None;
+    }
+
+    url.host_str().and_then(|host| {
+        percent_decode(host.as_bytes()).decode_utf8().ok().and_then(|path| {
+            if path.is_empty() {
+                None
+            } else {
+                Some(PathBuf::from(path.into_owned()))
+            }
+        })
+    })
+}
+
+#[cfg(unix)]
+pub use self::unix::UnixConnector;
+
+#[cfg(unix)]
+mod unix {
+    use std::io::{Error, ErrorKind};
+
+    use futures::future;
+
+    use tokio_core::reactor::Handle;
+    use tokio_uds::UnixStream;
+
+    use tokio_io::IoFuture;
+
+    use url::Url;
+
+    use super::{Connect, socket_path};
+
+    /// Connector that opens connections to Unix domain sockets.
+    ///
+    /// See `socket_path` for the format of the URLs accepted by this connector.
+    #[derive(Clone)]
+    pub struct UnixConnector {
+        handle: Handle,
+    }
+
+    impl UnixConnector {
+        /// Creates a new Unix domain socket connector using the given reactor handle.
+        pub fn new(handle: &Handle) -> UnixConnector {
+            UnixConnector { handle: handle.clone() }
+        }
+    }
+
+    impl Connect for UnixConnector {
+        type Io = UnixStream;
+
+        fn connect(&self, url: &Url) -> IoFuture<UnixStream> {
+            let result = socket_path(url)
+                .ok_or(Error::new(ErrorKind::InvalidInput, "not a Unix domain socket URL"))
+                .and_then(|path| {
+                    debug!("connecting to {}", path.display());
+                    UnixStream::connect(path, &self.handle)
+                });
+            Box::new(future::result(result))
+        }
+    }
+}
+
+#[cfg(feature = "tls")]
+pub use self::tls::{HttpsConnector, MaybeTlsStream};
+
+#[cfg(feature = "tls")]
+mod tls {
+    use std::io::{self, Error, ErrorKind, Read, Write};
+    use std::time::Instant;
+
+    use futures::{Future, Poll, future};
+
+    use native_tls;
+    use tokio_tls::{TlsConnector, TlsStream};
+
+    use tokio_io::{IoFuture, AsyncRead, AsyncWrite};
+
+    use url::Url;
+
+    use metrics::Recorder;
+    use super::Connect;
+
+    /// Connector that negotiates TLS for `https` and `wss` URLs on top of the connections
+    /// opened by another connector.
+    pub struct HttpsConnector<C> {
+        inner: C,
+        tls: TlsConnector,
+    }
+
+    impl<C: Connect> HttpsConnector<C> {
+        /// Creates a new HTTPS connector on top of `inner`, with the given TLS configuration.
+        ///
+        /// For `Client::http2` to use HTTP/2, the configuration must request the `h2` and
+        /// `http/1.1` protocols with `TlsConnectorBuilder::request_alpns`.
+        pub fn new(inner: C, tls: native_tls::TlsConnector) -> HttpsConnector<C> {
+            HttpsConnector {
+                inner: inner,
+                tls: TlsConnector::from(tls),
+            }
+        }
+    }
+
+    impl<C: Connect> Connect for HttpsConnector<C> {
+        type Io = MaybeTlsStream<C::Io>;
+
+        fn connect(&self, url: &Url) -> IoFuture<Self::Io> {
+            self.connect_timed(url, &Recorder::new())
+        }
+
+        fn connect_timed(&self, url: &Url, recorder: &Recorder) -> IoFuture<Self::Io> {
+            let connection = self.inner.connect_timed(url, recorder);
+            if url.scheme() != "https" && url.scheme() != "wss" {
+                return Box::new(connection.map(MaybeTlsStream::Plain));
+            }
+
+            let domain = match url.host_str() {
+                Some(domain) => domain.to_string(),
+                None => return Box::new(future::err(Error::new(ErrorKind::InvalidInput, "no host"))),
+            };
+
+            let tls = self.tls.clone();
+            let recorder = recorder.clone();
+            Box::new(connection.and_then(move |io| {
+                debug!("TLS handshake with {}", domain);
+                let start = Instant::now();
+                tls.connect(&domain, io).map(move |io| {
+                    trace!("TLS handshake with {} done in {:?}", domain, start.elapsed());
+                    recorder.tls_established();
+                    MaybeTlsStream::Tls(io)
+                }).map_err(Error::other)
+            }))
+        }
+
+        fn negotiated_protocol(&self, io: &Self::Io) -> Option<Vec<u8>> {
+            match *io {
+                MaybeTlsStream::Plain(_) => None,
+                MaybeTlsStream::Tls(ref io) => io.get_ref().negotiated_alpn().unwrap_or(None),
+            }
+        }
+    }
+
+    /// Connection that may or may not be encrypted with TLS.
+    pub enum MaybeTlsStream<T> {
+        Plain(T),
+        Tls(TlsStream<T>),
+    }
+
+    impl<T: Read + Write> Read for MaybeTlsStream<T> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match *self {
+                MaybeTlsStream::Plain(ref mut io) => io.read(buf),
+                MaybeTlsStream::Tls(ref mut io) => io.read(buf),
+            }
+        }
+    }
+
+    impl<T: Read + Write> Write for MaybeTlsStream<T> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            match *self {
+                MaybeTlsStream::Plain(ref mut io) => io.write(buf),
+                MaybeTlsStream::Tls(ref mut io) => io.write(buf),
+            }
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            match *self {
+                MaybeTlsStream::Plain(ref mut io) => io.flush(),
+                MaybeTlsStream::Tls(ref mut io) => io.flush(),
+            }
+        }
+    }
+
+    impl<T: AsyncRead + AsyncWrite> AsyncRead for MaybeTlsStream<T> {}
+
+    impl<T: AsyncRead + AsyncWrite> AsyncWrite for MaybeTlsStream<T> {
+        fn shutdown(&mut self) -> Poll<(), Error> {
+            match *self {
+                MaybeTlsStream::Plain(ref mut io) => io.shutdown(),
+                MaybeTlsStream::Tls(ref mut io) => io.shutdown(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Error, ErrorKind};
+    use std::net::TcpListener;
+    #[cfg(unix)]
+    use std::io::{Read, Write};
+    #[cfg(unix)]
+    use std::os::unix::net::UnixListener;
+    #[cfg(unix)]
+    use std::thread;
+
+    use url::{ParseError, Url};
+    #[cfg(unix)]
+    use url::percent_encoding::{utf8_percent_encode, PATH_SEGMENT_ENCODE_SET};
+
+    use prelude::*;
+    #[cfg(unix)]
+    use {Client, HttpRequest};
+    #[cfg(unix)]
+    use mock::TempPath;
+    use super::{HttpConnector, Connect, connect_any, socket_path};
+    #[cfg(unix)]
+    use super::UnixConnector;
+
+    #[test]
+    fn each_address() {
+        // nothing listens on the first address
+        let refused = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut core = Core::new().unwrap();
+        let remote = core.remote();
+        let no_address = Error::new(ErrorKind::UnexpectedEof, "no address");
+        let io = core.run(connect_any(vec![refused, addr].into_iter(), remote.clone(), no_address)).unwrap();
+        assert_eq!(io.peer_addr().unwrap(), addr);
+
+        let no_address = Error::new(ErrorKind::UnexpectedEof, "no address");
+        let err = core.run(connect_any(vec![refused].into_iter(), remote.clone(), no_address)).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+
+        let no_address = Error::new(ErrorKind::UnexpectedEof, "no address");
+        let err = core.run(connect_any(vec![].into_iter(), remote, no_address)).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+
+        let url = Url::parse(&format!("http://localhost:{}/", addr.port())).unwrap();
+        let connector = HttpConnector::new(&core.handle());
+        assert!(core.run(connector.connect(&url)).is_ok());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn parse_socket_path() {
+        let url = Url::parse("http+unix://%2Fvar%2Frun%2Fdocker.sock/containers/json").unwrap();
+        assert_eq!(socket_path(&url).unwrap().to_str(), Some("/var/run/docker.sock"));
+
+        let url = Url::parse("unix://%2Ftmp%2Fapp.sock/").unwrap();
+        assert_eq!(socket_path(&url).unwrap().to_str(), Some("/tmp/app.sock"));
+
+        assert!(socket_path(&Url::parse("unix:///tmp/app.sock").unwrap()).is_none());
+        let err = HttpRequest::get("unix:///var/run/docker.sock").err().unwrap();
+        assert_eq!(err, ParseError::EmptyHost);
+        assert_eq!(err.to_string(), "empty host");
+        assert!(socket_path(&Url::parse("http://localhost/").unwrap()).is_none());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn unix_socket() {
+        let path = TempPath::new("docker.sock");
+        let listener = UnixListener::bind(&*path).unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut head = vec![];
+            let mut byte = [0u8; 1];
+            while !head.ends_with(b"\r\n\r\n") {
+                stream.read_exact(&mut byte).unwrap();
+                head.push(byte[0]);
+            }
+            assert!(head.starts_with(b"GET /containers/json?all=1 HTTP/1.1\r\nHost: localhost\r\n"));
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n[]").unwrap();
+        });
+
+        let encoded = utf8_percent_encode(path.to_str().unwrap(), PATH_SEGMENT_ENCODE_SET);
+        let url = format!("http+unix://{}/containers/json?all=1", encoded);
+
+        let mut core = Core::new().unwrap();
+        let client = Client::with_connector(UnixConnector::new(&core.handle()));
+        let res = core.run(client.get(url)).unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.get_body(), b"[]");
+    }
+}
@@ -0,0 +1,171 @@
+//! HTTP-date parsing and formatting (RFC 7231 section 7.1.1.1).
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Parses an HTTP-date in any of the three formats that recipients must accept:
+///
+/// - IMF-fixdate: `Sun, 06 Nov 1994 08:49:37 GMT`
+/// - obsolete RFC 850 format: `Sunday, 06-Nov-94 08:49:37 GMT`
+/// - ANSI C's asctime() format: `Sun Nov  6 08:49:37 1994`
+///
+/// Returns `None` if the date is invalid, before 1970 or after 9999.
+pub fn parse(date: &str) -> Option<SystemTime> {
+    let tokens = date.split_whitespace().collect::<Vec<_>>();
+    let (year, month, day, time) = match tokens.len() {
+        // IMF-fixdate
+        6 if tokens[0].ends_with(',') && tokens[5] == "GMT" => {
+            (tokens[3].parse().ok()?, month(tokens[2])?, tokens[1], tokens[4])
+        }
+        // RFC 850
+        4 if tokens[0].ends_with(',') && tokens[3] == "GMT" => {
+            let mut parts = tokens[1].split('-');
+            let day = parts.next()?;
+            let month = month(parts.next()?)?;
+            let year = parts.next()?;
+            if year.len() != 2 || parts.next().is_some() {
+                return None;
+            }
+            let year = year.parse::<i64>().ok()?;
+            (if year < 70 { 2000 + year } else { 1900 + year }, month, day, tokens[2])
+        }
+        // asctime
+        5 => (tokens[4].parse().ok()?, month(tokens[1])?, tokens[2], tokens[3]),
+        _ => return None,
+    };
+
+    let day = day.parse::<u32>().ok()?;
+    if !(1..=31).contains(&day) || !(1970..=9999).contains(&year) {
+        return None;
+    }
+
+    let mut parts = time.split(':');
+    let mut next = |max: u64| {
+        parts.next().and_then(|part| {
+            if part.len() == 2 {
+                part.parse::<u64>().ok().and_then(|value| if value <= max { Some(value) } else { None })
+            } else {
+                None
+            }
+        })
+    };
+    // allow leap seconds
+    let seconds = next(23)? * 3600 + next(59)? * 60 + next(60)?;
+
+    let days = days_from_civil(year, month, day);
+    if days < 0 {
+        return None;
+    }
+    let secs = (days as u64).checked_mul(86400)?.checked_add(seconds)?;
+    UNIX_EPOCH.checked_add(Duration::from_secs(secs))
+}
+
+/// Formats the given time as an IMF-fixdate, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+///
+/// Times before 1970 are formatted as the epoch.
+pub fn format(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let days = (secs / 86400) as i64;
+    let seconds = secs % 86400;
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        DAYS[((days + 4) % 7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Formats the given time as an ISO 8601 UTC date and time with milliseconds, e.g.
+/// `1994-11-06T08:49:37.000Z`.
+///
+/// Times before 1970 are formatted as the epoch.
+pub fn format_iso8601(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let seconds = secs % 86400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+fn month(name: &str) -> Option<u32> {
+    MONTHS.iter().position(|&month| month == name).map(|index| index as u32 + 1)
+}
+
+/// Returns the number of days since 1970-01-01 of the given date.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let yoe = year - era * 400;
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Returns the date (year, month, day) of the given number of days since 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = if days >= 0 { days } else { days - 146_096 } / 146_097;
+    let doe = days - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{format, format_iso8601, parse};
+
+    #[test]
+    fn formats() {
+        let time = UNIX_EPOCH + Duration::from_secs(784_111_777);
+        assert_eq!(parse("Sun, 06 Nov 1994 08:49:37 GMT"), Some(time));
+        assert_eq!(parse("Sunday, 06-Nov-94 08:49:37 GMT"), Some(time));
+        assert_eq!(parse("Sun Nov  6 08:49:37 1994"), Some(time));
+        assert_eq!(format(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+
+        let time = UNIX_EPOCH + Duration::from_secs(1_709_210_096);
+        assert_eq!(parse("Thu, 29 Feb 2024 12:34:56 GMT"), Some(time));
+        assert_eq!(parse("Thursday, 29-Feb-24 12:34:56 GMT"), Some(time));
+        assert_eq!(format(time), "Thu, 29 Feb 2024 12:34:56 GMT");
+        assert_eq!(format_iso8601(time + Duration::from_millis(7)), "2024-02-29T12:34:56.007Z");
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("0"), None);
+        assert_eq!(parse("Sun, 06 Nov 1994 08:49:37 UTC"), None);
+        assert_eq!(parse("Sun, 06 Foo 1994 08:49:37 GMT"), None);
+        assert_eq!(parse("Sun, 06 Nov 1994 25:49:37 GMT"), None);
+        assert_eq!(parse("Sun, 06 Nov 1969 08:49:37 GMT"), None);
+        assert_eq!(parse("Sun, 06 Nov 400000000000 08:49:37 GMT"), None);
+        assert_eq!(parse("Sun Nov  6 08:49:37 99999999999999999"), None);
+        assert_eq!(parse("Sun Nov  6 08:49:37 -999999999999999999"), None);
+    }
+}
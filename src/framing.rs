@@ -0,0 +1,249 @@
+//! Framing of message bodies, shared by the codecs of both APIs.
+
+use std::cmp;
+use std::io::{Error, ErrorKind};
+use std::str;
+
+use HttpResponse;
+#[cfg(feature = "legacy")]
+use HttpRequest;
+
+/// Buffer from which messages are decoded: the `BytesMut` of bytes 0.4 with the legacy
+/// feature, and of bytes 1 with the tokio1 feature.
+pub trait Buffer: AsRef<[u8]> {
+    /// Data removed from the buffer.
+    type Data: AsRef<[u8]>;
+
+    /// Removes the first `len` bytes of the buffer, and returns them.
+    fn take(&mut self, len: usize) -> Self::Data;
+
+    /// Removes the first `len` bytes of the buffer.
+    fn skip(&mut self, len: usize);
+}
+
+#[cfg(feature = "legacy")]
+impl Buffer for ::bytes::BytesMut {
+    type Data = ::bytes::Bytes;
+
+    fn take(&mut self, len: usize) -> ::bytes::Bytes {
+        self.split_to(len).freeze()
+    }
+
+    fn skip(&mut self, len: usize) {
+        self.split_to(len);
+    }
+}
+
+#[cfg(feature = "tokio1")]
+impl Buffer for ::bytes1::BytesMut {
+    type Data = ::bytes1::Bytes;
+
+    fn take(&mut self, len: usize) -> ::bytes1::Bytes {
+        self.split_to(len).freeze()
+    }
+
+    fn skip(&mut self, len: usize) {
+        ::bytes1::Buf::advance(self, len);
+    }
+}
+
+/// State of a body decoder.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// Body with a `Content-Length`, and the number of bytes left.
+    Length(u64),
+    /// Body delimited by the end of the connection.
+    Close,
+    /// Chunked body, before the size of a chunk.
+    ChunkSize,
+    /// Chunked body, and the number of bytes left in the current chunk.
+    ChunkData(u64),
+    /// Chunked body, before the CRLF that ends a chunk.
+    ChunkEnd,
+    /// Chunked body, before the end of the trailer section.
+    Trailers,
+    Done,
+}
+
+/// How the body of a message is delimited, and how much of it was decoded.
+///
+/// Bodies are decoded incrementally from a buffer, each byte being parsed once however
+/// the data is split between reads.
+#[derive(Clone, Copy, Debug)]
+pub struct Framing {
+    state: State,
+}
+
+/// Result of `Framing::decode`.
+#[derive(Debug)]
+pub enum Decoded<D> {
+    /// Data of the body.
+    Data(D),
+    /// More data is needed.
+    Incomplete,
+    /// The body is complete.
+    End,
+}
+
+impl Framing {
+    /// Returns the framing of an empty body.
+    pub fn empty() -> Framing {
+        Framing { state: State::Done }
+    }
+
+    /// Returns the framing of a body of `len` bytes.
+    pub fn length(len: u64) -> Framing {
+        Framing { state: State::Length(len) }
+    }
+
+    /// Returns the framing of a chunked body.
+    pub fn chunked() -> Framing {
+        Framing { state: State::ChunkSize }
+    }
+
+    /// Returns the framing of a body delimited by the end of the connection.
+    pub fn close() -> Framing {
+        Framing { state: State::Close }
+    }
+
+    /// Returns the framing of the body of the given response, which answers a HEAD
+    /// request if `head` is true.
+    pub fn of_response(res: &HttpResponse, head: bool) -> Result<Framing, Error> {
+        if head || res.is_informational() || res.status() == 204 || res.status() == 304 {
+            return Ok(Framing::empty());
+        }
+        if res.has("Transfer-Encoding", "chunked") {
+            return Ok(Framing::chunked());
+        }
+        let lengths = res.headers().iter().filter(|header| header.name().eq_ignore_ascii_case("Content-Length"));
+        match content_length(lengths.map(|header| header.value().unwrap_or("")))? {
+            Some(len) => Ok(Framing::length(len)),
+            None => Ok(Framing::close()),
+        }
+    }
+
+    /// Returns the framing of the body of the given request, which is empty unless the
+    /// request has a `Content-Length` or is chunked.
+    ///
+    /// Requests with both `Transfer-Encoding` and `Content-Length`, or with different
+    /// lengths, are rejected, since another server could delimit them differently.
+    #[cfg(feature = "legacy")]
+    pub fn of_request(req: &HttpRequest) -> Result<Framing, Error> {
+        let lengths = req.headers.iter().filter(|header| header.0.eq_ignore_ascii_case("Content-Length"));
+        let length = content_length(lengths.map(|header| &*header.1))?;
+        match (req.get_header("Transfer-Encoding"), length) {
+            (Some(_), Some(_)) => {
+                Err(Error::new(ErrorKind::InvalidData, "request with both Transfer-Encoding and Content-Length"))
+            }
+            (Some(coding), None) => {
+                let last = coding.rsplit(',').next().unwrap_or("");
+                if !last.trim().eq_ignore_ascii_case("chunked") {
+                    return Err(Error::new(ErrorKind::InvalidData, "request body is not chunked"));
+                }
+                Ok(Framing::chunked())
+            }
+            (None, Some(len)) => Ok(Framing::length(len)),
+            (None, None) => Ok(Framing::empty()),
+        }
+    }
+
+    /// Removes the next data of the body from the start of `buf`.
+    ///
+    /// Data past the end of the body is left in `buf`.
+    pub fn decode<B: Buffer>(&mut self, buf: &mut B) -> Result<Decoded<B::Data>, Error> {
+        loop {
+            let available = buf.as_ref().len();
+            match self.state {
+                State::Done => return Ok(Decoded::End),
+                State::Length(0) => {
+                    trace!("end of body");
+                    self.state = State::Done;
+                }
+                State::Length(left) | State::ChunkData(left) => {
+                    if available == 0 {
+                        return Ok(Decoded::Incomplete);
+                    }
+                    let len = cmp::min(left, available as u64);
+                    self.state = match self.state {
+                        State::Length(_) => State::Length(left - len),
+                        _ if len == left => State::ChunkEnd,
+                        _ => State::ChunkData(left - len),
+                    };
+                    return Ok(Decoded::Data(buf.take(len as usize)));
+                }
+                State::Close if available == 0 => return Ok(Decoded::Incomplete),
+                State::Close => return Ok(Decoded::Data(buf.take(available))),
+                State::ChunkSize => match line(buf.as_ref()) {
+                    Some(len) => {
+                        let size = chunk_size(&buf.as_ref()[..len])?;
+                        buf.skip(len + 2);
+                        trace!("chunk of {} bytes", size);
+                        self.state = if size == 0 { State::Trailers } else { State::ChunkData(size) };
+                    }
+                    None => return Ok(Decoded::Incomplete),
+                },
+                State::ChunkEnd => match line(buf.as_ref()) {
+                    Some(0) => {
+                        buf.skip(2);
+                        self.state = State::ChunkSize;
+                    }
+                    Some(_) => return Err(Error::new(ErrorKind::InvalidData, "invalid chunk end")),
+                    None => return Ok(Decoded::Incomplete),
+                },
+                // trailers are ignored
+                State::Trailers => match line(buf.as_ref()) {
+                    Some(len) => {
+                        buf.skip(len + 2);
+                        if len == 0 {
+                            trace!("end of chunked body");
+                            self.state = State::Done;
+                        }
+                    }
+                    None => return Ok(Decoded::Incomplete),
+                },
+            }
+        }
+    }
+
+    /// Ends the body at the end of the connection, which fails unless the body is
+    /// complete or delimited by the end of the connection.
+    pub fn decode_eof(&mut self) -> Result<(), Error> {
+        match self.state {
+            State::Close | State::Done | State::Length(0) => {
+                trace!("end of body at end of connection");
+                self.state = State::Done;
+                Ok(())
+            }
+            _ => Err(Error::new(ErrorKind::UnexpectedEof, "connection closed before end of body")),
+        }
+    }
+}
+
+/// Returns the length given by the values of the `Content-Length` headers of a message.
+///
+/// Repeated values, as in `Content-Length: 5, 5`, are accepted only if they are equal.
+fn content_length<'a, I: Iterator<Item = &'a str>>(values: I) -> Result<Option<u64>, Error> {
+    let mut length = None;
+    for value in values.flat_map(|value| value.split(',')) {
+        let len = value.trim().parse::<u64>().map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        if length.map_or(false, |length| length != len) {
+            return Err(Error::new(ErrorKind::InvalidData, "conflicting Content-Length values"));
+        }
+        length = Some(len);
+    }
+    Ok(length)
+}
+
+/// Returns the length of the complete line at the start of `buf`, without its CRLF.
+fn line(buf: &[u8]) -> Option<usize> {
+    buf.windows(2).position(|window| window == b"\r\n")
+}
+
+fn chunk_size(line: &[u8]) -> Result<u64, Error> {
+    // chunk extensions are ignored
+    let size = line.split(|&c| c == b';').next().unwrap_or(line);
+    str::from_utf8(size)
+        .ok()
+        .and_then(|size| u64::from_str_radix(size.trim(), 16).ok())
+        .ok_or(Error::new(ErrorKind::InvalidData, "invalid chunk size"))
+}
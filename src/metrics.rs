@@ -0,0 +1,440 @@
+//! Timings of exchanges, and metrics aggregated over the exchanges of a client.
+//!
+//! Each response carries the timings of its exchange (`HttpResponse::timings`). A client
+//! also reports every exchange to the hook given to `Client::metrics`, such as `Metrics`,
+//! which aggregates them per host:
+//!
+//! ```no-run
+//! let metrics = Metrics::new();
+//! let client = Client::new(&handle).metrics(metrics.clone());
+//! ...
+//! let host = metrics.host("example.com").unwrap();
+//! println!("{} requests, p99 {:?}", host.requests(), host.latency().percentile(99.0));
+//! ```
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use Method;
+
+/// Timing breakdown of an exchange, as durations since the request was issued.
+///
+/// Phases that did not happen are `None`, e.g. the TLS handshake of a plain connection,
+/// or the connection of a request sent over a reused one.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Timings {
+    dns: Option<Duration>,
+    connect: Option<Duration>,
+    tls: Option<Duration>,
+    request_sent: Option<Duration>,
+    first_byte: Option<Duration>,
+    complete: Option<Duration>,
+    reused: bool,
+}
+
+impl Timings {
+    /// Returns the time until the host name was resolved.
+    pub fn dns(&self) -> Option<Duration> {
+        self.dns
+    }
+
+    /// Returns the time until the connection was established.
+    pub fn connect(&self) -> Option<Duration> {
+        self.connect
+    }
+
+    /// Returns the time until the TLS handshake was done.
+    pub fn tls(&self) -> Option<Duration> {
+        self.tls
+    }
+
+    /// Returns the time until the request was written.
+    pub fn request_sent(&self) -> Option<Duration> {
+        self.request_sent
+    }
+
+    /// Returns the time until the head of the response was received.
+    pub fn first_byte(&self) -> Option<Duration> {
+        self.first_byte
+    }
+
+    /// Returns the time until the body of the response was received, which is `None` for
+    /// streaming responses, whose body is read after they are returned.
+    pub fn complete(&self) -> Option<Duration> {
+        self.complete
+    }
+
+    /// Returns true if the request was sent over a connection opened for a previous one.
+    ///
+    /// Only HTTP/2 connections are reused, so this is always false for HTTP/1.1.
+    pub fn is_reused(&self) -> bool {
+        self.reused
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    timings: Timings,
+    bytes_sent: u64,
+    bytes_received: u64,
+}
+
+/// Records the timings and sizes of an exchange as it progresses.
+///
+/// Connectors mark the phases of a connection in `Connect::connect_timed`.
+#[derive(Clone, Debug)]
+pub struct Recorder {
+    start: Instant,
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for Recorder {
+    fn default() -> Recorder {
+        Recorder::new()
+    }
+}
+
+impl Recorder {
+    /// Creates a recorder of an exchange that starts now.
+    pub fn new() -> Recorder {
+        Recorder {
+            start: Instant::now(),
+            state: Arc::new(Mutex::new(State::default())),
+        }
+    }
+
+    /// Returns the time since the exchange started.
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn mark<F>(&self, phase: F)
+    where
+        F: FnOnce(&mut Timings) -> &mut Option<Duration>,
+    {
+        let elapsed = self.start.elapsed();
+        *phase(&mut self.state.lock().unwrap().timings) = Some(elapsed);
+    }
+
+    /// Marks the host name as resolved.
+    pub fn dns_resolved(&self) {
+        self.mark(|timings| &mut timings.dns)
+    }
+
+    /// Marks the connection as established.
+    pub fn connected(&self) {
+        self.mark(|timings| &mut timings.connect)
+    }
+
+    /// Marks the TLS handshake as done.
+    pub fn tls_established(&self) {
+        self.mark(|timings| &mut timings.tls)
+    }
+
+    /// Marks the request as written.
+    pub fn request_sent(&self) {
+        self.mark(|timings| &mut timings.request_sent)
+    }
+
+    /// Marks the head of the response as received.
+    pub fn first_byte(&self) {
+        self.mark(|timings| &mut timings.first_byte)
+    }
+
+    /// Marks the body of the response as received.
+    pub fn completed(&self) {
+        self.mark(|timings| &mut timings.complete)
+    }
+
+    /// Marks the connection as reused.
+    pub fn reused(&self) {
+        self.state.lock().unwrap().timings.reused = true;
+    }
+
+    /// Adds `len` bytes to the size of the request body.
+    pub fn sent(&self, len: usize) {
+        self.state.lock().unwrap().bytes_sent += len as u64;
+    }
+
+    /// Adds `len` bytes to the size of the response body.
+    pub fn received(&self, len: usize) {
+        self.state.lock().unwrap().bytes_received += len as u64;
+    }
+
+    /// Returns the timings recorded so far.
+    pub fn timings(&self) -> Timings {
+        self.state.lock().unwrap().timings
+    }
+
+    /// Returns the summary of the exchange with `host`, whose response has the given
+    /// status, or `None` if it failed or its body was dropped before its end.
+    pub fn exchange(&self, host: String, method: Method, status: Option<u32>) -> Exchange {
+        let state = self.state.lock().unwrap();
+        Exchange {
+            host: host,
+            method: method,
+            status: status,
+            bytes_sent: state.bytes_sent,
+            bytes_received: state.bytes_received,
+            timings: state.timings,
+        }
+    }
+}
+
+/// Summary of an exchange, reported to the metrics hook of a client once the body of
+/// the response has been received, or the exchange has failed or was cancelled.
+#[derive(Clone, Debug)]
+pub struct Exchange {
+    host: String,
+    method: Method,
+    status: Option<u32>,
+    bytes_sent: u64,
+    bytes_received: u64,
+    timings: Timings,
+}
+
+impl Exchange {
+    /// Returns the host the request was sent to.
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Returns the method of the request.
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+
+    /// Returns the status of the response, or `None` if the exchange failed or was
+    /// cancelled by dropping the body of the response.
+    pub fn status(&self) -> Option<u32> {
+        self.status
+    }
+
+    /// Returns the number of bytes of the request body that were sent.
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent
+    }
+
+    /// Returns the number of bytes of the response body that were received.
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received
+    }
+
+    /// Returns the timings of the exchange.
+    pub fn timings(&self) -> &Timings {
+        &self.timings
+    }
+}
+
+/// Hook that is given a summary of every exchange of a client.
+pub trait MetricsHook: Send + Sync {
+    /// Records the given exchange.
+    fn record(&self, exchange: &Exchange);
+}
+
+impl<F: Fn(&Exchange) + Send + Sync> MetricsHook for F {
+    fn record(&self, exchange: &Exchange) {
+        self(exchange)
+    }
+}
+
+/// Upper bounds of the buckets of latency histograms, in milliseconds.
+const BUCKETS: [u64; 13] = [1, 2, 5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+/// Histogram of latencies, with buckets from 1 ms to 10 s.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Histogram {
+    counts: [u64; 14],
+    count: u64,
+    sum: Duration,
+    max: Duration,
+}
+
+impl Histogram {
+    /// Adds a latency to this histogram.
+    pub fn record(&mut self, latency: Duration) {
+        let millis = latency.as_secs() * 1000 + u64::from(latency.subsec_millis());
+        let bucket = BUCKETS.iter().position(|&bound| millis < bound).unwrap_or(BUCKETS.len());
+        self.counts[bucket] += 1;
+        self.count += 1;
+        self.sum += latency;
+        if latency > self.max {
+            self.max = latency;
+        }
+    }
+
+    /// Returns the number of latencies in this histogram.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns the sum of the latencies in this histogram.
+    pub fn sum(&self) -> Duration {
+        self.sum
+    }
+
+    /// Returns the highest latency in this histogram.
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// Returns the mean latency, or `None` if this histogram is empty.
+    pub fn mean(&self) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        let nanos = (self.sum.as_secs() * 1_000_000_000 + u64::from(self.sum.subsec_nanos())) / self.count;
+        Some(Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32))
+    }
+
+    /// Returns the upper bound of each bucket, `None` for the last one, and the number
+    /// of latencies below it and above the previous bound.
+    pub fn buckets(&self) -> Vec<(Option<Duration>, u64)> {
+        let bounds = BUCKETS.iter().map(|&bound| Some(Duration::from_millis(bound)));
+        bounds.chain(Some(None)).zip(self.counts.iter().cloned()).collect()
+    }
+
+    /// Returns an upper bound of the given percentile of the latencies, i.e. the upper
+    /// bound of its bucket, or the highest latency for the last bucket.
+    pub fn percentile(&self, percentile: f64) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((percentile / 100.0) * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, &count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Some(match BUCKETS.get(bucket) {
+                    Some(&bound) => Duration::from_millis(bound),
+                    None => self.max,
+                });
+            }
+        }
+        Some(self.max)
+    }
+}
+
+/// Metrics of the exchanges with a host.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct HostMetrics {
+    requests: u64,
+    errors: u64,
+    status_classes: [u64; 5],
+    bytes_sent: u64,
+    bytes_received: u64,
+    latency: Histogram,
+}
+
+impl HostMetrics {
+    /// Returns the number of exchanges.
+    pub fn requests(&self) -> u64 {
+        self.requests
+    }
+
+    /// Returns the number of exchanges that failed without a response.
+    pub fn errors(&self) -> u64 {
+        self.errors
+    }
+
+    /// Returns the number of responses in the given status class, e.g. 2 for 2xx.
+    pub fn status_class(&self, class: u32) -> u64 {
+        if (1..=5).contains(&class) {
+            self.status_classes[class as usize - 1]
+        } else {
+            0
+        }
+    }
+
+    /// Returns the number of bytes of request bodies sent.
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent
+    }
+
+    /// Returns the number of bytes of response bodies received.
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received
+    }
+
+    /// Returns the histogram of the durations of the exchanges that succeeded, until
+    /// their response was complete.
+    pub fn latency(&self) -> &Histogram {
+        &self.latency
+    }
+}
+
+/// Metrics hook that aggregates exchanges per host.
+///
+/// Clones share the same metrics, so that a clone can be given to a client and the
+/// metrics read from the original.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    hosts: Arc<Mutex<HashMap<String, HostMetrics>>>,
+}
+
+impl Metrics {
+    /// Creates empty metrics.
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    /// Returns the metrics of the given host, if a request was sent to it.
+    pub fn host(&self, host: &str) -> Option<HostMetrics> {
+        self.hosts.lock().unwrap().get(host).cloned()
+    }
+
+    /// Returns the metrics of all hosts.
+    pub fn hosts(&self) -> HashMap<String, HostMetrics> {
+        self.hosts.lock().unwrap().clone()
+    }
+}
+
+impl MetricsHook for Metrics {
+    fn record(&self, exchange: &Exchange) {
+        let mut hosts = self.hosts.lock().unwrap();
+        let host = hosts.entry(exchange.host.clone()).or_default();
+        host.requests += 1;
+        host.bytes_sent += exchange.bytes_sent;
+        host.bytes_received += exchange.bytes_received;
+        match exchange.status {
+            Some(status) => {
+                if (100..600).contains(&status) {
+                    host.status_classes[status as usize / 100 - 1] += 1;
+                }
+                let timings = &exchange.timings;
+                if let Some(latency) = timings.complete.or(timings.first_byte) {
+                    host.latency.record(latency);
+                }
+            }
+            None => host.errors += 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Histogram;
+
+    #[test]
+    fn histogram() {
+        let mut histogram = Histogram::default();
+        assert_eq!(histogram.percentile(50.0), None);
+
+        for &millis in &[3, 4, 20, 30, 40, 700, 12000] {
+            histogram.record(Duration::from_millis(millis));
+        }
+        assert_eq!(histogram.count(), 7);
+        assert_eq!(histogram.mean(), Some(Duration::new(1, 828_142_857)));
+        assert_eq!(histogram.percentile(25.0), Some(Duration::from_millis(5)));
+        assert_eq!(histogram.percentile(50.0), Some(Duration::from_millis(50)));
+        assert_eq!(histogram.percentile(99.0), Some(Duration::from_secs(12)));
+
+        let buckets = histogram.buckets();
+        assert_eq!(buckets.len(), 14);
+        assert_eq!(buckets[2], (Some(Duration::from_millis(5)), 2));
+        assert_eq!(buckets[13], (None, 1));
+    }
+}
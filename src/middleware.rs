@@ -0,0 +1,243 @@
+//! Middleware around the requests sent by a `Client`.
+//!
+//! Middleware are added to a client with `Client::middleware`, and may modify requests
+//! before they are sent, and responses before they are returned. Responses go through
+//! the middleware as soon as their head is received, along with a stream of their body:
+//!
+//! ```no-run
+//! let client = Client::new(&handle)
+//!     .middleware(UserAgent::default())
+//!     .middleware(|req: HttpRequest, next: Next| {
+//!         Box::new(next.run(req).map(|(res, body)| { println!("{}", res.status()); (res, body) }))
+//!             as StreamingResponse
+//!     });
+//! ```
+
+use std::borrow::Cow;
+use std::fmt::Write;
+use std::rc::Rc;
+
+use rand;
+
+use client::StreamingResponse;
+use HttpRequest;
+
+/// Middleware that handles a request, usually by passing it to the next middleware.
+pub trait Middleware {
+    /// Handles the given request, and returns its response and a stream of its body.
+    fn handle(&self, req: HttpRequest, next: Next) -> StreamingResponse;
+}
+
+impl<F> Middleware for F
+where
+    F: Fn(HttpRequest, Next) -> StreamingResponse,
+{
+    fn handle(&self, req: HttpRequest, next: Next) -> StreamingResponse {
+        self(req, next)
+    }
+}
+
+/// Rest of a chain of middleware, which ends by sending the request.
+#[derive(Clone)]
+pub struct Next {
+    middlewares: Rc<Vec<Rc<dyn Middleware>>>,
+    index: usize,
+    send: Rc<dyn Fn(HttpRequest) -> StreamingResponse>,
+}
+
+impl Next {
+    /// Creates a chain of the given middleware, in order, that ends by sending requests
+    /// with `send`.
+    pub fn new<F>(middlewares: Vec<Rc<dyn Middleware>>, send: F) -> Next
+    where
+        F: 'static + Fn(HttpRequest) -> StreamingResponse,
+    {
+        Next {
+            middlewares: Rc::new(middlewares),
+            index: 0,
+            send: Rc::new(send),
+        }
+    }
+
+    /// Passes the given request to the next middleware, or sends it if there is none left.
+    pub fn run(self, req: HttpRequest) -> StreamingResponse {
+        match self.middlewares.get(self.index).cloned() {
+            Some(middleware) => {
+                let next = Next {
+                    middlewares: self.middlewares,
+                    index: self.index + 1,
+                    send: self.send,
+                };
+                middleware.handle(req, next)
+            }
+            None => (self.send)(req),
+        }
+    }
+}
+
+/// Middleware that adds headers to the requests that do not have them.
+#[derive(Clone, Debug, Default)]
+pub struct DefaultHeaders {
+    headers: Vec<(Cow<'static, str>, Cow<'static, str>)>,
+}
+
+impl DefaultHeaders {
+    /// Creates a new middleware without any header.
+    pub fn new() -> DefaultHeaders {
+        DefaultHeaders { headers: vec![] }
+    }
+
+    /// Adds a header, which is sent unless the request has a header with the same name.
+    pub fn header<K, V>(mut self, name: K, value: V) -> DefaultHeaders
+    where
+        K: Into<Cow<'static, str>>,
+        V: Into<Cow<'static, str>>,
+    {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+}
+
+impl Middleware for DefaultHeaders {
+    fn handle(&self, mut req: HttpRequest, next: Next) -> StreamingResponse {
+        for &(ref name, ref value) in &self.headers {
+            if req.get_header(name).is_none() {
+                req = req.header(name.clone(), value.clone());
+            }
+        }
+        next.run(req)
+    }
+}
+
+/// Middleware that sets the `User-Agent` header of the requests that do not have one.
+#[derive(Clone, Debug)]
+pub struct UserAgent {
+    value: Cow<'static, str>,
+}
+
+impl UserAgent {
+    /// Creates a new middleware that identifies requests with the given product.
+    pub fn new<V: Into<Cow<'static, str>>>(value: V) -> UserAgent {
+        UserAgent { value: value.into() }
+    }
+}
+
+impl Default for UserAgent {
+    /// Identifies requests as sent by this crate, e.g. `async-http-client/0.2.0`.
+    fn default() -> UserAgent {
+        UserAgent::new(concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")))
+    }
+}
+
+impl Middleware for UserAgent {
+    fn handle(&self, req: HttpRequest, next: Next) -> StreamingResponse {
+        if req.get_header("User-Agent").is_some() {
+            return next.run(req);
+        }
+        next.run(req.header("User-Agent", self.value.clone()))
+    }
+}
+
+/// Middleware that gives each request a random identifier, to correlate it with the
+/// logs of the server.
+#[derive(Clone, Debug)]
+pub struct RequestId {
+    header: &'static str,
+}
+
+impl RequestId {
+    /// Creates a new middleware that sends identifiers in an `X-Request-ID` header.
+    pub fn new() -> RequestId {
+        RequestId::with_header("X-Request-ID")
+    }
+
+    /// Creates a new middleware that sends identifiers in the given header.
+    pub fn with_header(header: &'static str) -> RequestId {
+        RequestId { header: header }
+    }
+}
+
+impl Default for RequestId {
+    fn default() -> RequestId {
+        RequestId::new()
+    }
+}
+
+impl Middleware for RequestId {
+    fn handle(&self, req: HttpRequest, next: Next) -> StreamingResponse {
+        if req.get_header(self.header).is_some() {
+            return next.run(req);
+        }
+
+        let mut id = String::with_capacity(32);
+        for _ in 0..16 {
+            write!(id, "{:02x}", rand::random::<u8>()).unwrap();
+        }
+        next.run(req.header(self.header, id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use futures::{Future, Stream};
+
+    use body::BodyStream;
+    use client::StreamingResponse;
+    use mock::MockConnector;
+    use response::{headers_mut, new_header};
+    use {Client, HttpRequest};
+    use super::{DefaultHeaders, Next, RequestId, UserAgent};
+
+    #[test]
+    fn chain() {
+        let output = Arc::new(Mutex::new(vec![]));
+        let client = Client::with_connector(MockConnector::new(
+            vec![b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"],
+            &output,
+        ))
+            .middleware(|req: HttpRequest, next: Next| -> StreamingResponse {
+                Box::new(next.run(req.header("X-Order", "first")).map(|(mut res, body)| {
+                    headers_mut(&mut res).push(new_header("X-Seen", "yes"));
+                    (res, body)
+                }))
+            })
+            .middleware(|req: HttpRequest, next: Next| next.run(req.header("X-Order", "second")))
+            .middleware(DefaultHeaders::new().header("Accept", "text/plain").header("X-Default", "1"))
+            .middleware(UserAgent::new("test/1.0"))
+            .middleware(RequestId::new());
+
+        let req = HttpRequest::get("http://example.com/").unwrap().header("X-Default", "2");
+        let res = client.request(req).wait().unwrap();
+        assert!(res.is("X-Seen", "yes"));
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.starts_with(
+            "GET / HTTP/1.1\r\nHost: example.com\r\nX-Default: 2\r\nX-Order: first\r\n\
+             X-Order: second\r\nAccept: text/plain\r\nUser-Agent: test/1.0\r\nX-Request-ID: "
+        ));
+        let id = output.split("X-Request-ID: ").nth(1).unwrap();
+        assert_eq!(id.find("\r\n"), Some(32));
+    }
+
+    #[test]
+    fn streaming() {
+        let output = Arc::new(Mutex::new(vec![]));
+        let client = Client::with_connector(MockConnector::new(
+            vec![b"HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\nbody"],
+            &output,
+        ))
+            .middleware(|req: HttpRequest, next: Next| -> StreamingResponse {
+                Box::new(next.run(req).map(|(res, body)| {
+                    (res, Box::new(body.map(|chunk| chunk.to_ascii_uppercase().into())) as BodyStream)
+                }))
+            })
+            .middleware(UserAgent::new("test/1.0"));
+
+        let req = HttpRequest::get("http://example.com/").unwrap();
+        let (_, body) = client.request_streaming(req).wait().unwrap();
+        assert_eq!(&body.concat2().wait().unwrap()[..], b"BODY");
+        assert!(String::from_utf8(output.lock().unwrap().clone()).unwrap().contains("User-Agent: test/1.0"));
+    }
+}
@@ -0,0 +1,117 @@
+//! In-memory connections for tests.
+
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::io::{self, Cursor, Read, Write};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use futures::{Poll, future};
+
+use tokio_io::{IoFuture, AsyncRead, AsyncWrite};
+
+use url::Url;
+
+use connect::Connect;
+
+/// In-memory connection that replays a canned response and records what is written.
+pub struct Mock {
+    input: Cursor<Vec<u8>>,
+    output: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Read for Mock {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for Mock {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncRead for Mock {}
+
+impl AsyncWrite for Mock {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        Ok(().into())
+    }
+}
+
+/// Connector that answers each new connection with the next canned response.
+pub struct MockConnector {
+    responses: RefCell<Vec<&'static [u8]>>,
+    output: Arc<Mutex<Vec<u8>>>,
+}
+
+impl MockConnector {
+    pub fn new(responses: Vec<&'static [u8]>, output: &Arc<Mutex<Vec<u8>>>) -> MockConnector {
+        MockConnector {
+            responses: RefCell::new(responses),
+            output: output.clone(),
+        }
+    }
+}
+
+impl Connect for MockConnector {
+    type Io = Mock;
+
+    fn connect(&self, url: &Url) -> IoFuture<Mock> {
+        assert_eq!(url.host_str(), Some("example.com"));
+        Box::new(future::ok(Mock {
+            input: Cursor::new(self.responses.borrow_mut().remove(0).to_vec()),
+            output: self.output.clone(),
+        }))
+    }
+}
+
+/// Path in the temporary directory, unique to the test that created it, whose file or
+/// directory is removed when it is dropped, even if the test fails.
+pub struct TempPath {
+    path: PathBuf,
+}
+
+impl TempPath {
+    /// Returns a new path whose file name ends with `name`.
+    pub fn new(name: &str) -> TempPath {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let count = COUNT.fetch_add(1, Ordering::SeqCst);
+        let file_name = format!("async-http-client-{}-{}-{}", process::id(), count, name);
+        TempPath { path: env::temp_dir().join(file_name) }
+    }
+}
+
+impl Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = if self.path.is_dir() {
+            fs::remove_dir_all(&self.path)
+        } else {
+            fs::remove_file(&self.path)
+        };
+    }
+}
@@ -0,0 +1,364 @@
+//! `multipart/form-data` request bodies (RFC 7578).
+
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+use futures::{Async, Poll, Stream, stream};
+
+use bytes::Bytes;
+
+use tokio_io::AsyncRead;
+
+use url::percent_encoding::{utf8_percent_encode, QUERY_ENCODE_SET};
+
+use rand;
+
+use body::{BodyStream, read_file, read_stream};
+
+/// Content of a part.
+enum Content {
+    Bytes(Vec<u8>),
+    File(PathBuf, u64),
+    Stream(BodyStream, Option<u64>),
+}
+
+impl Content {
+    fn len(&self) -> Option<u64> {
+        match *self {
+            Content::Bytes(ref bytes) => Some(bytes.len() as u64),
+            Content::File(_, len) => Some(len),
+            Content::Stream(_, len) => len,
+        }
+    }
+
+    fn into_stream(self) -> BodyStream {
+        match self {
+            Content::Bytes(bytes) => Box::new(stream::once(Ok(bytes.into()))),
+            // files are read lazily, when the body is sent
+            Content::File(path, len) => checked(read_file(path), len),
+            Content::Stream(stream, Some(len)) => checked(stream, len),
+            Content::Stream(stream, None) => stream,
+        }
+    }
+}
+
+/// Fails `stream` if it does not yield exactly `len` bytes, since that length was
+/// already announced in the `Content-Length` of the body.
+fn checked(stream: BodyStream, len: u64) -> BodyStream {
+    Box::new(Checked {
+        stream: stream,
+        left: len,
+    })
+}
+
+struct Checked {
+    stream: BodyStream,
+    left: u64,
+}
+
+impl Stream for Checked {
+    type Item = Bytes;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Bytes>, Error> {
+        match self.stream.poll()? {
+            Async::Ready(Some(chunk)) => match self.left.checked_sub(chunk.len() as u64) {
+                Some(left) => {
+                    self.left = left;
+                    Ok(Async::Ready(Some(chunk)))
+                }
+                None => Err(Error::new(ErrorKind::InvalidData, "part is longer than its announced length")),
+            },
+            Async::Ready(None) if self.left > 0 => {
+                Err(Error::new(ErrorKind::UnexpectedEof, "part is shorter than its announced length"))
+            }
+            Async::Ready(None) => Ok(Async::Ready(None)),
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
+
+/// Part of a `multipart/form-data` body.
+pub struct Part {
+    name: String,
+    filename: Option<String>,
+    content_type: Option<String>,
+    headers: Vec<(String, String)>,
+    content: Content,
+}
+
+impl Part {
+    fn new(name: String, content: Content) -> Part {
+        Part {
+            name: name,
+            filename: None,
+            content_type: None,
+            headers: vec![],
+            content: content,
+        }
+    }
+
+    /// Creates a new text part.
+    pub fn text<N: Into<String>, V: Into<String>>(name: N, value: V) -> Part {
+        Part::new(name.into(), Content::Bytes(value.into().into_bytes()))
+    }
+
+    /// Creates a new part with the given content.
+    pub fn bytes<N: Into<String>, B: Into<Vec<u8>>>(name: N, bytes: B) -> Part {
+        Part::new(name.into(), Content::Bytes(bytes.into()))
+            .content_type("application/octet-stream")
+    }
+
+    /// Creates a new part with the content of the file at the given path.
+    ///
+    /// The file name defaults to the last component of the path. The file itself
+    /// is only read when the body is sent, with blocking reads on the event loop; the
+    /// body fails if the file no longer has the length it had when the part was created.
+    pub fn file<N: Into<String>, P: AsRef<Path>>(name: N, path: P) -> Result<Part> {
+        let path = path.as_ref();
+        let len = fs::metadata(path)?.len();
+        let part = Part::new(name.into(), Content::File(path.to_path_buf(), len))
+            .content_type("application/octet-stream");
+        Ok(match path.file_name() {
+            Some(filename) => part.filename(filename.to_string_lossy().into_owned()),
+            None => part,
+        })
+    }
+
+    /// Creates a new part with the content read from `reader`, whose length
+    /// may be known in advance.
+    ///
+    /// If a length is given, the body fails if `reader` yields more or fewer bytes.
+    pub fn reader<N, R>(name: N, reader: R, length: Option<u64>) -> Part
+    where
+        N: Into<String>,
+        R: 'static + AsyncRead + Send,
+    {
+        Part::new(name.into(), Content::Stream(read_stream(reader), length))
+            .content_type("application/octet-stream")
+    }
+
+    /// Sets the file name of this part.
+    pub fn filename<F: Into<String>>(mut self, filename: F) -> Part {
+        self.filename = Some(filename.into());
+        self
+    }
+
+    /// Sets the content type of this part.
+    pub fn content_type<T: Into<String>>(mut self, content_type: T) -> Part {
+        self.content_type = Some(content_type.into());
+        self
+    }
+
+    /// Adds a header to this part.
+    pub fn header<K: Into<String>, V: Into<String>>(mut self, name: K, value: V) -> Part {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Returns the headers of this part, including the blank line that ends them.
+    fn head(&self) -> String {
+        let mut head = format!("Content-Disposition: form-data; name=\"{}\"", escape(&self.name));
+        if let Some(ref filename) = self.filename {
+            head.push_str(&format!("; filename=\"{}\"", escape(filename)));
+            if !filename.is_ascii() {
+                let encoded = utf8_percent_encode(filename, QUERY_ENCODE_SET)
+                    .to_string()
+                    .replace('\'', "%27");
+                head.push_str(&format!("; filename*=UTF-8''{}", encoded));
+            }
+        }
+        head.push_str("\r\n");
+        if let Some(ref content_type) = self.content_type {
+            head.push_str(&format!("Content-Type: {}\r\n", content_type));
+        }
+        for &(ref name, ref value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+        head
+    }
+}
+
+/// Escapes a name or file name so that it can be put between quotes.
+fn escape(value: &str) -> String {
+    value.replace('"', "%22").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+/// Builder of `multipart/form-data` bodies.
+pub struct Multipart {
+    boundary: String,
+    parts: Vec<Part>,
+}
+
+impl Default for Multipart {
+    fn default() -> Multipart {
+        Multipart::new()
+    }
+}
+
+impl Multipart {
+    /// Creates a new multipart body with a random boundary.
+    pub fn new() -> Multipart {
+        let boundary = format!("{:016x}{:016x}", rand::random::<u64>(), rand::random::<u64>());
+        Multipart::with_boundary(boundary)
+    }
+
+    /// Creates a new multipart body with the given boundary.
+    pub fn with_boundary<B: Into<String>>(boundary: B) -> Multipart {
+        Multipart {
+            boundary: boundary.into(),
+            parts: vec![],
+        }
+    }
+
+    /// Returns the boundary between parts.
+    pub fn boundary(&self) -> &str {
+        &self.boundary
+    }
+
+    /// Adds a part.
+    pub fn part(mut self, part: Part) -> Multipart {
+        self.parts.push(part);
+        self
+    }
+
+    /// Adds a text field.
+    pub fn text<N: Into<String>, V: Into<String>>(self, name: N, value: V) -> Multipart {
+        self.part(Part::text(name, value))
+    }
+
+    /// Adds a file part with the content of the file at the given path.
+    pub fn file<N: Into<String>, P: AsRef<Path>>(self, name: N, path: P) -> Result<Multipart> {
+        Ok(self.part(Part::file(name, path)?))
+    }
+
+    /// Returns the value of the `Content-Type` header for this body.
+    pub fn content_type(&self) -> String {
+        format!("multipart/form-data; boundary={}", self.boundary)
+    }
+
+    /// Returns the length of this body, if the length of all its parts is known.
+    pub fn content_length(&self) -> Option<u64> {
+        let mut length = self.closing().len() as u64;
+        for part in &self.parts {
+            length += (self.delimiter().len() + part.head().len() + 2) as u64;
+            length += part.content.len()?;
+        }
+        Some(length)
+    }
+
+    fn delimiter(&self) -> String {
+        format!("--{}\r\n", self.boundary)
+    }
+
+    fn closing(&self) -> String {
+        format!("--{}--\r\n", self.boundary)
+    }
+
+    /// Returns this body as a stream.
+    pub fn into_stream(self) -> BodyStream {
+        let delimiter = self.delimiter();
+        let closing = self.closing();
+
+        let mut streams: Vec<BodyStream> = vec![];
+        for part in self.parts {
+            let head = format!("{}{}", delimiter, part.head());
+            streams.push(Box::new(stream::once(Ok(Bytes::from(head)))));
+            streams.push(part.content.into_stream());
+            streams.push(Box::new(stream::once(Ok(Bytes::from_static(b"\r\n")))));
+        }
+        streams.push(Box::new(stream::once(Ok(Bytes::from(closing)))));
+
+        Box::new(stream::iter_ok::<_, Error>(streams).flatten())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::{Cursor, ErrorKind, Write};
+
+    use futures::{Future, Stream};
+
+    use tokio_io::io::AllowStdIo;
+
+    use HttpRequest;
+    use mock::TempPath;
+    use super::{Multipart, Part};
+
+    fn collect(multipart: Multipart) -> String {
+        let body = multipart.into_stream().concat2().wait().unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn fields_and_files() {
+        let path = TempPath::new("report.txt");
+        File::create(&*path).unwrap().write_all(b"file content").unwrap();
+
+        let multipart = Multipart::with_boundary("XyZ")
+            .text("title", "My \"report\"")
+            .file("attachment", &*path)
+            .unwrap()
+            .part(
+                Part::bytes("data", vec![1, 2, 3])
+                    .filename("résumé.bin")
+                    .header("Content-Language", "fr"),
+            );
+        assert_eq!(multipart.content_type(), "multipart/form-data; boundary=XyZ");
+
+        let length = multipart.content_length().unwrap();
+        let filename = path.file_name().unwrap().to_str().unwrap().to_string();
+        let body = collect(multipart);
+
+        assert_eq!(body.len() as u64, length);
+        assert_eq!(
+            body,
+            format!(
+                "--XyZ\r\n\
+                 Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+                 My \"report\"\r\n\
+                 --XyZ\r\n\
+                 Content-Disposition: form-data; name=\"attachment\"; filename=\"{}\"\r\n\
+                 Content-Type: application/octet-stream\r\n\r\n\
+                 file content\r\n\
+                 --XyZ\r\n\
+                 Content-Disposition: form-data; name=\"data\"; filename=\"résumé.bin\"; \
+                 filename*=UTF-8''r%C3%A9sum%C3%A9.bin\r\n\
+                 Content-Type: application/octet-stream\r\n\
+                 Content-Language: fr\r\n\r\n\
+                 \u{1}\u{2}\u{3}\r\n\
+                 --XyZ--\r\n",
+                filename
+            )
+        );
+    }
+
+    #[test]
+    fn unknown_length() {
+        let reader = AllowStdIo::new(Cursor::new(b"streamed".to_vec()));
+        let multipart = Multipart::new().part(Part::reader("upload", reader, None));
+        assert!(multipart.content_length().is_none());
+        assert_eq!(multipart.boundary().len(), 32);
+
+        let req = HttpRequest::post("http://localhost/upload", vec![]).unwrap().multipart(multipart);
+        let head = format!("{}", req);
+        assert!(head.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(!head.contains("Content-Length"));
+    }
+
+    #[test]
+    fn length_mismatch() {
+        let reader = AllowStdIo::new(Cursor::new(b"streamed".to_vec()));
+        let multipart = Multipart::new().part(Part::reader("upload", reader, Some(4)));
+        let err = multipart.into_stream().concat2().wait().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let reader = AllowStdIo::new(Cursor::new(b"streamed".to_vec()));
+        let multipart = Multipart::new().part(Part::reader("upload", reader, Some(10)));
+        let err = multipart.into_stream().concat2().wait().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+}
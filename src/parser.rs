@@ -1,4 +1,4 @@
-//! Parser module for HTTP response.
+//! Parser module for HTTP responses and requests.
 
 use nom::{IResult, ErrorKind, crlf, is_digit, is_space};
 use nom::IResult::{Done, Error};
@@ -12,6 +12,11 @@ fn is_token(c: u8) -> bool {
     c > 32 && c < 127 && c != b':'
 }
 
+/// Returns true for the characters of a request-target.
+fn is_target(c: u8) -> bool {
+    c > 32 && c < 127
+}
+
 fn parse_code(input: &[u8]) -> IResult<&[u8], u32> {
     if input.iter().all(|&c| is_digit(c)) {
         let sum = input.iter().fold(
@@ -85,10 +90,56 @@ named!(header_field<Header>,
     )
 );
 
+/// Request line and header fields of a request.
+pub struct RequestHead {
+    pub method: String,
+    pub target: String,
+    pub version: (u32, u32),
+    pub headers: Vec<Header>,
+}
+
+named!(request_line<(String, String, (u32, u32))>,
+    do_parse!(
+        method: take_while1!(is_token) >>
+        char!(' ') >>
+        target: take_while1!(is_target) >>
+        char!(' ') >>
+        tag!("HTTP/") >>
+        major: take_while1!(is_digit) >>
+        char!('.') >>
+        minor: take_while1!(is_digit) >>
+        crlf >>
+        ({
+            // this is safe because these only contain 32 < c < 127
+            let method = unsafe { str::from_utf8_unchecked(method) }.to_string();
+            let target = unsafe { str::from_utf8_unchecked(target) }.to_string();
+            let major = unsafe { str::from_utf8_unchecked(major) }.parse().unwrap_or(0);
+            let minor = unsafe { str::from_utf8_unchecked(minor) }.parse().unwrap_or(0);
+            (method, target, (major, minor))
+        })
+    )
+);
+
+named!(pub request<RequestHead>,
+    do_parse!(
+        line: request_line >>
+        headers: many0!(header_field) >>
+        crlf >>
+        ({
+            RequestHead {
+                method: line.0,
+                target: line.1,
+                version: line.2,
+                headers: headers
+            }
+        })
+    )
+);
+
 named!(pub response<HttpResponse>,
     do_parse!(
         status: status_line >>
-        headers: many1!(header_field) >>
+        headers: many0!(header_field) >>
         crlf >>
         ({
             new_response(status.version(), status.code, headers)
@@ -98,7 +149,7 @@ named!(pub response<HttpResponse>,
 
 #[cfg(test)]
 mod tests {
-    use super::response;
+    use super::{request, response};
 
     #[test]
     fn test_response() {
@@ -135,4 +186,32 @@ mod tests {
         assert!(res.has("trAnSfeR-enCodIng", "gzip"));
         assert!(!res.has("Transfer-Encoding", "deflate"));
     }
+
+    #[test]
+    fn test_no_headers() {
+        let (rest, res) = response(b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1").unwrap();
+        assert_eq!(res.status(), 100);
+        assert!(res.headers().is_empty());
+        assert_eq!(rest, &b"HTTP/1.1"[..]);
+
+        assert!(response(b"HTTP/1.1 200 OK\r\nContent-Le").is_incomplete());
+    }
+
+    #[test]
+    fn test_request() {
+        let (rest, head) = request(
+            b"POST /items?page=2 HTTP/1.1\r\n\
+            Host: localhost:3000\r\n\
+            Content-Length: 3\r\n\
+            \r\n\
+            abc",
+        ).unwrap();
+        assert_eq!(rest, &b"abc"[..]);
+        assert_eq!((&*head.method, &*head.target, head.version), ("POST", "/items?page=2", (1, 1)));
+        assert_eq!(head.headers.len(), 2);
+        assert_eq!(head.headers[1].value(), Some("3"));
+
+        assert!(request(b"GET / HTTP/1.1\r\nHost: a").is_incomplete());
+        assert!(request(b"GET /a b HTTP/1.1\r\n\r\n").is_err());
+    }
 }
@@ -0,0 +1,256 @@
+//! Range requests (RFC 7233).
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::ops::{Range, RangeFrom, RangeInclusive};
+
+/// Range of bytes requested in a `Range` header, which holds at least one byte.
+///
+/// Ranges are built from `first..`, with `ByteRange::try_from` for bounded ranges, or
+/// with the `new` and `last` constructors.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ByteRange(Spec);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Spec {
+    /// Bytes from the first to the last position, inclusive.
+    FromTo(u64, u64),
+    /// Bytes from the given position to the end.
+    From(u64),
+    /// The given number of bytes at the end.
+    Last(u64),
+}
+
+impl ByteRange {
+    /// Returns the range of bytes from `first` to `last`, inclusive.
+    ///
+    /// Fails if `last` is before `first`.
+    pub fn new(first: u64, last: u64) -> Result<ByteRange, Error> {
+        if first > last {
+            return Err(Error::new(ErrorKind::InvalidInput, "empty range of bytes"));
+        }
+        Ok(ByteRange(Spec::FromTo(first, last)))
+    }
+
+    /// Returns the range of the last `len` bytes.
+    ///
+    /// Fails if `len` is zero.
+    pub fn last(len: u64) -> Result<ByteRange, Error> {
+        if len == 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "empty range of bytes"));
+        }
+        Ok(ByteRange(Spec::Last(len)))
+    }
+}
+
+/// Fails if the range is empty, since a range of bytes holds at least one byte.
+impl TryFrom<Range<u64>> for ByteRange {
+    type Error = Error;
+
+    fn try_from(range: Range<u64>) -> Result<ByteRange, Error> {
+        if range.start >= range.end {
+            return Err(Error::new(ErrorKind::InvalidInput, "empty range of bytes"));
+        }
+        ByteRange::new(range.start, range.end - 1)
+    }
+}
+
+/// Fails if the range is empty, since a range of bytes holds at least one byte.
+impl TryFrom<RangeInclusive<u64>> for ByteRange {
+    type Error = Error;
+
+    fn try_from(range: RangeInclusive<u64>) -> Result<ByteRange, Error> {
+        ByteRange::new(*range.start(), *range.end())
+    }
+}
+
+impl From<RangeFrom<u64>> for ByteRange {
+    fn from(range: RangeFrom<u64>) -> ByteRange {
+        ByteRange(Spec::From(range.start))
+    }
+}
+
+impl fmt::Display for ByteRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Spec::FromTo(first, last) => write!(f, "{}-{}", first, last),
+            Spec::From(first) => write!(f, "{}-", first),
+            Spec::Last(len) => write!(f, "-{}", len),
+        }
+    }
+}
+
+/// Range of bytes sent in a response, as found in `Content-Range` headers.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ContentRange {
+    /// Bytes from the first to the last position, inclusive, of a representation
+    /// whose length may be unknown.
+    Bytes {
+        first: u64,
+        last: u64,
+        complete_length: Option<u64>,
+    },
+    /// None of the requested ranges could be satisfied (416 Range Not Satisfiable).
+    Unsatisfied { complete_length: u64 },
+}
+
+impl ContentRange {
+    /// Parses a `Content-Range` value such as `bytes 0-499/1234`.
+    pub fn parse(value: &str) -> Option<ContentRange> {
+        let value = value.trim();
+        if value.len() < 6 || !value[..6].eq_ignore_ascii_case("bytes ") {
+            return None;
+        }
+
+        let mut parts = value[6..].trim_start().splitn(2, '/');
+        let range = parts.next()?;
+        let complete_length = match parts.next()? {
+            "*" => None,
+            length => Some(length.parse().ok()?),
+        };
+
+        if range == "*" {
+            return complete_length.map(|complete_length| {
+                ContentRange::Unsatisfied { complete_length: complete_length }
+            });
+        }
+
+        let mut positions = range.splitn(2, '-');
+        let first: u64 = positions.next()?.parse().ok()?;
+        let last: u64 = positions.next()?.parse().ok()?;
+        if last < first || complete_length.map_or(false, |length| last >= length) {
+            return None;
+        }
+        // the length of the range must fit in a u64
+        (last - first).checked_add(1)?;
+        Some(ContentRange::Bytes {
+            first: first,
+            last: last,
+            complete_length: complete_length,
+        })
+    }
+
+    /// Returns the number of bytes in this range.
+    pub fn len(&self) -> u64 {
+        match *self {
+            ContentRange::Bytes { first, last, .. } => last.saturating_sub(first).saturating_add(1),
+            ContentRange::Unsatisfied { .. } => 0,
+        }
+    }
+
+    /// Returns true if this range is unsatisfied.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the length of the complete representation, if known.
+    pub fn complete_length(&self) -> Option<u64> {
+        match *self {
+            ContentRange::Bytes { complete_length, .. } => complete_length,
+            ContentRange::Unsatisfied { complete_length } => Some(complete_length),
+        }
+    }
+}
+
+impl fmt::Display for ContentRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ContentRange::Bytes { first, last, complete_length } => {
+                write!(f, "bytes {}-{}/", first, last)?;
+                match complete_length {
+                    Some(length) => write!(f, "{}", length),
+                    None => write!(f, "*"),
+                }
+            }
+            ContentRange::Unsatisfied { complete_length } => write!(f, "bytes */{}", complete_length),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use HttpRequest;
+    use response::{append, new_header, new_response};
+    use super::{ByteRange, ContentRange};
+
+    #[test]
+    fn ranges() {
+        let req = HttpRequest::get("http://example.com/").unwrap().range(500..);
+        assert!(req.to_string().contains("\r\nRange: bytes=500-\r\n"));
+
+        let req = HttpRequest::get("http://example.com/").unwrap().ranges(&[
+            ByteRange::try_from(0..500).unwrap(),
+            ByteRange::try_from(600..=699).unwrap(),
+            ByteRange::last(100).unwrap(),
+        ]);
+        assert!(req.to_string().contains("\r\nRange: bytes=0-499, 600-699, -100\r\n"));
+
+        assert!(ByteRange::try_from(0..0).is_err());
+        assert!(ByteRange::try_from(5..5).is_err());
+        assert_eq!(ByteRange::try_from(5..6).unwrap(), ByteRange::new(5, 5).unwrap());
+        let (first, last) = (5, 4);
+        assert!(ByteRange::try_from(first..=last).is_err());
+        assert!(ByteRange::new(5, 4).is_err());
+        assert!(ByteRange::last(0).is_err());
+    }
+
+    #[test]
+    fn content_range() {
+        let range = ContentRange::parse("bytes 21010-47021/47022").unwrap();
+        assert_eq!(range, ContentRange::Bytes {
+            first: 21010,
+            last: 47021,
+            complete_length: Some(47022),
+        });
+        assert_eq!(range.len(), 26012);
+        assert_eq!(range.to_string(), "bytes 21010-47021/47022");
+
+        assert_eq!(ContentRange::parse("bytes 0-9/*").unwrap().complete_length(), None);
+        assert_eq!(
+            ContentRange::parse("bytes */47022"),
+            Some(ContentRange::Unsatisfied { complete_length: 47022 })
+        );
+        assert_eq!(ContentRange::parse("bytes 9-0/10"), None);
+        assert_eq!(ContentRange::parse("bytes 0-10/10"), None);
+        assert_eq!(ContentRange::parse("bytes 0-18446744073709551615/*"), None);
+        assert_eq!(ContentRange::parse("bytes 1-18446744073709551615/*").unwrap().len(), u64::MAX);
+        assert_eq!(ContentRange::parse("items 0-1/2"), None);
+    }
+
+    #[test]
+    fn byteranges() {
+        let mut res = new_response((1, 1), 206, vec![
+            new_header("Content-Type", "multipart/byteranges; boundary=THIS_STRING_SEPARATES"),
+        ]);
+        append(&mut res, &b"\r\n--THIS_STRING_SEPARATES\r\n\
+            Content-Type: application/pdf\r\n\
+            Content-Range: bytes 500-505/8000\r\n\r\n\
+            \r\n--TH\r\n\
+            --THIS_STRING_SEPARATES\r\n\
+            Content-Type: application/pdf\r\n\
+            Content-Range: bytes 7000-7002/8000\r\n\r\n\
+            end\r\n\
+            --THIS_STRING_SEPARATES--\r\n"[..]);
+        let parts = res.byte_ranges().unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0], (ContentRange::parse("bytes 500-505/8000").unwrap(), &b"\r\n--TH"[..]));
+        assert_eq!(parts[1].1, b"end");
+
+        let mut res = new_response((1, 1), 206, vec![new_header("Content-Range", "bytes 2-4/10")]);
+        append(&mut res, b"abc");
+        assert_eq!(res.byte_ranges().unwrap(), vec![(ContentRange::parse("bytes 2-4/10").unwrap(), &b"abc"[..])]);
+
+        let res = new_response((1, 1), 200, vec![new_header("Content-Length", "0")]);
+        assert!(res.byte_ranges().is_err());
+
+        // a part longer than the body
+        let mut res = new_response((1, 1), 206, vec![
+            new_header("Content-Type", "multipart/byteranges; boundary=B"),
+        ]);
+        append(&mut res, &b"--B\r\nContent-Range: bytes 1-18446744073709551615/*\r\n\r\nab\r\n--B--\r\n"[..]);
+        assert_eq!(res.byte_ranges().unwrap_err().to_string(), "truncated part");
+    }
+}
@@ -0,0 +1,369 @@
+//! Client-side rate limiting with token buckets.
+//!
+//! `RateLimiter` is a middleware that delays the requests sent by a `Client`
+//! so that they stay within a global quota and per-host quotas, instead of failing them:
+//!
+//! ```no-run
+//! let limiter = RateLimiter::new()
+//!     .global(Quota::per_second(20))
+//!     .host("api.github.com", Quota::per_minute(60).burst(10))
+//!     .adaptive(true);
+//! let client = Client::new(&handle).middleware(limiter);
+//! ```
+//!
+//! When adaptive, the limiter also waits for the delay of the `Retry-After` header of
+//! 429 and 503 responses, and for the reset of the `RateLimit-*` or `X-RateLimit-*`
+//! headers once the server reports that no request remains.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use futures::Future;
+
+use client::StreamingResponse;
+use middleware::{Middleware, Next};
+use timer;
+use {HttpRequest, HttpResponse};
+
+/// Number of requests allowed per period, with bursts of up to `burst` requests.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Quota {
+    requests: u32,
+    period: Duration,
+    burst: u32,
+}
+
+impl Quota {
+    /// Allows `requests` requests per `period`, all of which can be sent at once.
+    ///
+    /// Panics if `requests` is zero.
+    pub fn new(requests: u32, period: Duration) -> Quota {
+        assert!(requests > 0, "a quota must allow at least one request");
+        Quota {
+            requests: requests,
+            period: period,
+            burst: requests,
+        }
+    }
+
+    /// Allows `requests` requests per second.
+    pub fn per_second(requests: u32) -> Quota {
+        Quota::new(requests, Duration::from_secs(1))
+    }
+
+    /// Allows `requests` requests per minute.
+    pub fn per_minute(requests: u32) -> Quota {
+        Quota::new(requests, Duration::from_secs(60))
+    }
+
+    /// Sets how many requests can be sent at once after a quiet period.
+    pub fn burst(mut self, burst: u32) -> Quota {
+        self.burst = cmp::max(burst, 1);
+        self
+    }
+
+    /// Returns the time it takes to earn one request.
+    fn interval(&self) -> Duration {
+        self.period / self.requests
+    }
+}
+
+/// Token bucket, whose tokens may be reserved in advance.
+#[derive(Debug)]
+struct Bucket {
+    quota: Option<Quota>,
+    /// Time at which the bucket will be full again, earlier if it is already full.
+    full_at: Instant,
+    /// Time before which no request may be sent, as told by the server.
+    blocked_until: Option<Instant>,
+}
+
+impl Bucket {
+    fn new(quota: Option<Quota>, now: Instant) -> Bucket {
+        Bucket {
+            quota: quota,
+            full_at: now,
+            blocked_until: None,
+        }
+    }
+
+    /// Takes a token, and returns the time at which the request it stands for may be sent.
+    fn reserve(&mut self, now: Instant) -> Instant {
+        let blocked_until = self.blocked_until.filter(|&until| until > now);
+        self.blocked_until = blocked_until;
+        let start = blocked_until.unwrap_or(now);
+
+        let quota = match self.quota {
+            Some(quota) => quota,
+            None => return start,
+        };
+
+        // the bucket is full at `full_at`, and holds `burst` tokens earned `interval` apart
+        let interval = quota.interval();
+        let window = interval * quota.burst;
+        let full_at = cmp::max(self.full_at, start);
+        let at = cmp::max(start, (full_at + interval).checked_sub(window).unwrap_or(start));
+        self.full_at = full_at + interval;
+        at
+    }
+
+    /// Blocks this bucket until `until`.
+    fn block(&mut self, until: Instant) {
+        self.blocked_until = Some(cmp::max(until, self.blocked_until.unwrap_or(until)));
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    global: Bucket,
+    hosts: HashMap<String, Bucket>,
+}
+
+/// Middleware that delays requests to stay within a global quota and per-host quotas.
+///
+/// Clones share the same buckets, so that one limiter can be shared by several clients.
+#[derive(Clone)]
+pub struct RateLimiter {
+    state: Arc<Mutex<State>>,
+    quotas: HashMap<String, Quota>,
+    per_host: Option<Quota>,
+    adaptive: bool,
+    max_delay: Duration,
+}
+
+impl Default for RateLimiter {
+    fn default() -> RateLimiter {
+        RateLimiter::new()
+    }
+}
+
+impl RateLimiter {
+    /// Creates a limiter without any quota.
+    pub fn new() -> RateLimiter {
+        RateLimiter {
+            state: Arc::new(Mutex::new(State {
+                global: Bucket::new(None, Instant::now()),
+                hosts: HashMap::new(),
+            })),
+            quotas: HashMap::new(),
+            per_host: None,
+            adaptive: false,
+            max_delay: Duration::from_secs(600),
+        }
+    }
+
+    /// Limits all requests, whatever their host.
+    pub fn global(self, quota: Quota) -> RateLimiter {
+        self.state.lock().unwrap().global.quota = Some(quota);
+        self
+    }
+
+    /// Limits the requests to the given host, instead of the quota set with `per_host`.
+    pub fn host<H: Into<String>>(mut self, host: H, quota: Quota) -> RateLimiter {
+        self.quotas.insert(host.into().to_ascii_lowercase(), quota);
+        self
+    }
+
+    /// Limits the requests to each host without a quota of its own.
+    pub fn per_host(mut self, quota: Quota) -> RateLimiter {
+        self.per_host = Some(quota);
+        self
+    }
+
+    /// Sets whether the limiter also waits for the delays given by the `Retry-After` and
+    /// `RateLimit-*` headers of the responses. Defaults to false.
+    pub fn adaptive(mut self, adaptive: bool) -> RateLimiter {
+        self.adaptive = adaptive;
+        self
+    }
+
+    /// Sets the longest delay the limiter waits for when a server asks for one, however
+    /// long the server asks for. Defaults to ten minutes.
+    pub fn max_delay(mut self, max_delay: Duration) -> RateLimiter {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Reserves a request to `host`, and returns how long to wait before sending it.
+    fn reserve(&self, host: &str, now: Instant) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let global = state.global.reserve(now);
+        let quota = self.quotas.get(host).cloned().or(self.per_host);
+        let host = state.hosts.entry(host.to_string()).or_insert_with(|| Bucket::new(quota, now)).reserve(now);
+        cmp::max(global, host).duration_since(now)
+    }
+
+    /// Blocks the requests to `host` for as long as `res` tells.
+    fn adapt(&self, host: &str, res: &HttpResponse, now: Instant) {
+        let until = match server_delay(res, SystemTime::now()) {
+            Some(delay) => match now.checked_add(cmp::min(delay, self.max_delay)) {
+                Some(until) => until,
+                None => return,
+            },
+            None => return,
+        };
+        let mut state = self.state.lock().unwrap();
+        let quota = self.quotas.get(host).cloned().or(self.per_host);
+        state.hosts.entry(host.to_string()).or_insert_with(|| Bucket::new(quota, now)).block(until);
+    }
+}
+
+impl Middleware for RateLimiter {
+    fn handle(&self, req: HttpRequest, next: Next) -> StreamingResponse {
+        let host = req.url().host_str().unwrap_or("").to_ascii_lowercase();
+        let delay = self.reserve(&host, Instant::now());
+        if delay > Duration::from_millis(0) {
+            debug!("delaying request to {} by {:?}", host, delay);
+        }
+
+        let limiter = self.clone();
+        let send = timer::sleep(delay).and_then(move |_| next.run(req));
+        Box::new(send.map(move |(res, body)| {
+            if limiter.adaptive {
+                limiter.adapt(&host, &res, Instant::now());
+            }
+            (res, body)
+        }))
+    }
+}
+
+/// Returns the value of the first of the given headers that is a number.
+fn number(res: &HttpResponse, names: &[&str]) -> Option<u64> {
+    names.iter().filter_map(|&name| res[name].as_ref()).filter_map(|value| value.trim().parse().ok()).next()
+}
+
+/// Returns how long the server asks to wait before the next request, if at all.
+fn server_delay(res: &HttpResponse, now: SystemTime) -> Option<Duration> {
+    if res.status() == 429 || res.status() == 503 {
+        if let Some(delay) = res.retry_after() {
+            return Some(delay);
+        }
+    }
+
+    let remaining = number(res, &["RateLimit-Remaining", "X-RateLimit-Remaining"])?;
+    if remaining > 0 {
+        return None;
+    }
+    let reset = number(res, &["RateLimit-Reset", "X-RateLimit-Reset"])?;
+
+    // a reset that is not a number of seconds is a Unix timestamp
+    let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    if reset > 1_000_000_000 {
+        Some(Duration::from_secs(reset.saturating_sub(since_epoch)))
+    } else {
+        Some(Duration::from_secs(reset))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+    use futures::Future;
+
+    use mock::MockConnector;
+    use response::{new_header, new_response};
+    use {Client, HttpRequest};
+    use super::{Bucket, Quota, RateLimiter, server_delay};
+
+    #[test]
+    fn bucket() {
+        let now = Instant::now();
+        let ms = Duration::from_millis;
+        let mut bucket = Bucket::new(Some(Quota::per_second(4).burst(2)), now);
+        assert_eq!(bucket.reserve(now), now);
+        assert_eq!(bucket.reserve(now), now);
+        assert_eq!(bucket.reserve(now), now + ms(250));
+        assert_eq!(bucket.reserve(now + ms(100)), now + ms(500));
+
+        // refilled after a quiet period, up to the burst
+        let later = now + ms(5000);
+        assert_eq!(bucket.reserve(later), later);
+        assert_eq!(bucket.reserve(later), later);
+        assert_eq!(bucket.reserve(later), later + ms(250));
+
+        bucket.block(later + ms(2000));
+        assert_eq!(bucket.reserve(later + ms(1000)), later + ms(2000));
+
+        let mut unlimited = Bucket::new(None, now);
+        assert_eq!(unlimited.reserve(now), now);
+        unlimited.block(now + ms(10));
+        assert_eq!(unlimited.reserve(now), now + ms(10));
+        assert_eq!(unlimited.reserve(now + ms(20)), now + ms(20));
+    }
+
+    #[test]
+    fn quotas() {
+        let limiter = RateLimiter::new()
+            .global(Quota::per_second(4))
+            .host("A.com", Quota::per_second(1))
+            .per_host(Quota::per_second(2));
+        let now = Instant::now();
+        let ms = Duration::from_millis;
+        assert_eq!(limiter.reserve("a.com", now), ms(0));
+        assert_eq!(limiter.reserve("a.com", now), ms(1000));
+        assert_eq!(limiter.reserve("b.com", now), ms(0));
+        assert_eq!(limiter.reserve("b.com", now), ms(0));
+        assert_eq!(limiter.reserve("b.com", now), ms(500));
+        // the five requests above used up the burst of the global quota, and one more token
+        assert_eq!(limiter.reserve("c.com", now), ms(500));
+    }
+
+    #[test]
+    fn headers() {
+        let res = |status, headers: &[(&str, &str)]| {
+            new_response((1, 1), status, headers.iter().map(|&(name, value)| new_header(name, value)).collect())
+        };
+        let now = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+        let secs = |secs| Some(Duration::from_secs(secs));
+
+        assert_eq!(server_delay(&res(429, &[("Retry-After", "3")]), now), secs(3));
+        assert_eq!(server_delay(&res(200, &[("Retry-After", "3")]), now), None);
+        assert_eq!(server_delay(&res(200, &[("RateLimit-Remaining", "0"), ("RateLimit-Reset", "7")]), now), secs(7));
+        assert_eq!(server_delay(&res(200, &[("RateLimit-Remaining", "1"), ("RateLimit-Reset", "7")]), now), None);
+        let reset = [("X-RateLimit-Remaining", "0"), ("X-RateLimit-Reset", "1500000042")];
+        assert_eq!(server_delay(&res(403, &reset), now), secs(42));
+        assert!(server_delay(&res(503, &[("Retry-After", "Fri, 31 Dec 1999 23:59:59 GMT")]), SystemTime::now()) == secs(0));
+
+        // huge delays are clamped, and do not overflow
+        let limiter = RateLimiter::new().max_delay(Duration::from_secs(60));
+        let now = Instant::now();
+        limiter.adapt("a.com", &res(429, &[("Retry-After", "18446744073709551615")]), now);
+        assert_eq!(limiter.reserve("a.com", now), Duration::from_secs(60));
+        let limiter = RateLimiter::new().max_delay(Duration::new(u64::MAX, 0));
+        limiter.adapt("a.com", &res(200, &[("RateLimit-Remaining", "0"), ("RateLimit-Reset", "18446744073709551615")]), now);
+        assert_eq!(limiter.reserve("a.com", now), Duration::from_millis(0));
+    }
+
+    #[test]
+    fn rate_limiter() {
+        let client = Client::with_connector(MockConnector::new(
+            vec![
+                b"HTTP/1.1 429 Too Many Requests\r\nRetry-After: 1\r\nContent-Length: 0\r\n\r\n",
+                b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n",
+                b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n",
+            ],
+            &Arc::new(Mutex::new(vec![])),
+        )).middleware(
+            RateLimiter::new()
+                .host("example.com", Quota::new(1, Duration::from_millis(200)))
+                .adaptive(true),
+        );
+
+        let get = |url| client.request(HttpRequest::get(url).unwrap()).wait().unwrap().status();
+        let start = Instant::now();
+        assert_eq!(get("http://example.com/"), 429);
+        assert!(start.elapsed() < Duration::from_millis(200));
+
+        // waits for the Retry-After delay, give or take the resolution of the timer
+        assert_eq!(get("http://example.com/"), 200);
+        assert!(start.elapsed() >= Duration::from_millis(900));
+
+        let sent = Instant::now();
+        assert_eq!(get("http://EXAMPLE.com/"), 200);
+        assert!(sent.elapsed() >= Duration::from_millis(100));
+    }
+}
@@ -1,19 +1,35 @@
 //! Definition of response structure.
 
-use std::cmp;
+use std::borrow::Cow;
+use std::convert::TryFrom;
 use std::fmt;
+use std::io::{Error, ErrorKind};
 use std::ops::Index;
+use std::time::{Duration, SystemTime};
+
+use encoding_rs::{Encoding, UTF_8};
+
+use conditional::ETag;
+use date;
+use metrics::Timings;
+use range::ContentRange;
+
+#[cfg(feature = "json")]
+use serde::de::DeserializeOwned;
+#[cfg(feature = "json")]
+use serde_json;
 
 /// Representation of a header.
 ///
 /// For convenience, the header value is trimmed at parsing time (optional spaces are
 /// removed from the beginning and the end of the value).
-#[derive(PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub struct Header {
     name: String,
     value: Option<String>,
 }
 
+#[cfg(any(test, feature = "legacy", feature = "tokio1"))]
 pub fn new_header<K: Into<String>, V: Into<String>>(name: K, value: V) -> Header {
     Header {
         name: name.into(),
@@ -21,6 +37,18 @@ pub fn new_header<K: Into<String>, V: Into<String>>(name: K, value: V) -> Header
     }
 }
 
+impl Header {
+    /// Returns the name of this header.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the value of this header.
+    pub fn value(&self) -> Option<&str> {
+        self.value.as_ref().map(|s| s.as_str())
+    }
+}
+
 impl fmt::Display for Header {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -33,29 +61,77 @@ impl fmt::Display for Header {
 }
 
 /// Representation of an HTTP response.
-#[derive(PartialEq, Eq, Debug)]
+///
+/// Responses are compared without their timings.
+#[derive(Clone, Debug)]
 pub struct HttpResponse {
     version: (u32, u32),
     status: u32,
     headers: Vec<Header>,
     body: Vec<u8>,
+    timings: Timings,
+}
+
+impl PartialEq for HttpResponse {
+    fn eq(&self, other: &HttpResponse) -> bool {
+        self.version == other.version && self.status == other.status && self.headers == other.headers &&
+            self.body == other.body
+    }
 }
 
+impl Eq for HttpResponse {}
+
+#[cfg(any(test, feature = "legacy", feature = "tokio1"))]
 pub fn new_response(version: (u32, u32), status: u32, headers: Vec<Header>) -> HttpResponse {
     HttpResponse {
         version: version,
         status: status,
         headers: headers,
         body: Vec::new(),
+        timings: Timings::default(),
     }
 }
 
 impl HttpResponse {
+    /// Creates an HTTP/1.1 response with the given status code, no headers and an
+    /// empty body, e.g. to be encoded by a `ServerCodec`.
+    pub fn new(status: u32) -> HttpResponse {
+        new_response((1, 1), status, vec![])
+    }
+
+    /// Adds a header to this response.
+    pub fn header<K: Into<String>, V: Into<String>>(mut self, name: K, value: V) -> HttpResponse {
+        self.headers.push(new_header(name, value));
+        self
+    }
+
+    /// Sets the body of this response.
+    pub fn body<B: Into<Vec<u8>>>(mut self, body: B) -> HttpResponse {
+        self.body = body.into();
+        self
+    }
+
+    /// Returns the HTTP version of this response, as (major, minor).
+    pub fn version(&self) -> (u32, u32) {
+        self.version
+    }
+
     /// Returns the status code of this response.
     pub fn status(&self) -> u32 {
         self.status
     }
 
+    /// Returns the headers of this response.
+    pub fn headers(&self) -> &[Header] {
+        &self.headers
+    }
+
+    /// Returns the timings of the exchange of this response, which are empty for
+    /// responses that were not received by a `Client`.
+    pub fn timings(&self) -> &Timings {
+        &self.timings
+    }
+
     /// Returns true if this response has a header with the given `name`
     /// that matches the expected `value`.
     ///
@@ -105,19 +181,219 @@ impl HttpResponse {
         self.status >= 500 && self.status < 600
     }
 
+    /// Returns true if this response has a 304 Not Modified status code.
+    pub fn is_not_modified(&self) -> bool {
+        self.status == 304
+    }
+
+    /// Returns the entity tag of the `ETag` header.
+    pub fn etag(&self) -> Option<ETag> {
+        self["ETag"].as_ref().and_then(|etag| ETag::parse(etag))
+    }
+
+    /// Returns the date of the `Last-Modified` header.
+    pub fn last_modified(&self) -> Option<SystemTime> {
+        self["Last-Modified"].as_ref().and_then(|value| date::parse(value))
+    }
+
+    /// Returns the date of the `Date` header.
+    pub fn date(&self) -> Option<SystemTime> {
+        self["Date"].as_ref().and_then(|value| date::parse(value))
+    }
+
+    /// Returns how long to wait before sending another request, given by the
+    /// `Retry-After` header in seconds or as an HTTP-date.
+    pub fn retry_after(&self) -> Option<Duration> {
+        self["Retry-After"].as_ref().and_then(|value| match value.parse::<u64>() {
+            Ok(seconds) => Some(Duration::from_secs(seconds)),
+            Err(_) => date::parse(value).map(|date| {
+                date.duration_since(SystemTime::now()).unwrap_or_default()
+            }),
+        })
+    }
+
+    /// Returns the range of the `Content-Range` header.
+    pub fn content_range(&self) -> Option<ContentRange> {
+        self["Content-Range"].as_ref().and_then(|value| ContentRange::parse(value))
+    }
+
+    /// Returns the ranges of bytes of a 206 Partial Content response along with their
+    /// content, whether the response has a single range or a `multipart/byteranges` body.
+    pub fn byte_ranges(&self) -> Result<Vec<(ContentRange, &[u8])>, Error> {
+        let invalid = |msg| Error::new(ErrorKind::InvalidData, msg);
+        if self.status != 206 {
+            return Err(invalid("not a 206 Partial Content response"));
+        }
+
+        if let Some(range) = self.content_range() {
+            if range.len() != self.body.len() as u64 {
+                return Err(invalid("body does not match Content-Range"));
+            }
+            return Ok(vec![(range, &self.body[..])]);
+        }
+
+        let is_multipart = self["Content-Type"].as_ref().map_or(false, |content_type| {
+            content_type.trim().to_ascii_lowercase().starts_with("multipart/byteranges")
+        });
+        let boundary = match self.content_type_param("boundary") {
+            Some(boundary) if is_multipart => boundary,
+            _ => return Err(invalid("no Content-Range")),
+        };
+
+        // the length of each part is given by its Content-Range
+        let delimiter = format!("\r\n--{}", boundary).into_bytes();
+        let body = &self.body[..];
+        let mut pos = find(body, &delimiter[2..]).ok_or(invalid("missing boundary"))? + delimiter.len() - 2;
+        let mut ranges = vec![];
+        while !body[pos..].starts_with(b"--") {
+            let end = find(&body[pos..], b"\r\n\r\n").ok_or(invalid("truncated part"))?;
+            let head = String::from_utf8_lossy(&body[pos..pos + end]);
+            let range = head.split("\r\n").filter_map(|line| {
+                let mut parts = line.splitn(2, ':');
+                match (parts.next(), parts.next()) {
+                    (Some(name), Some(value)) if name.trim().eq_ignore_ascii_case("Content-Range") => {
+                        ContentRange::parse(value)
+                    }
+                    _ => None,
+                }
+            }).next().ok_or(invalid("part without Content-Range"))?;
+
+            let start = pos + end + 4;
+            let data = usize::try_from(range.len())
+                .ok()
+                .and_then(|len| start.checked_add(len))
+                .and_then(|end| body.get(start..end))
+                .ok_or(invalid("truncated part"))?;
+            ranges.push((range, data));
+            pos = start + data.len();
+            if !body[pos..].starts_with(&delimiter) {
+                return Err(invalid("missing boundary"));
+            }
+            pos += delimiter.len();
+        }
+        Ok(ranges)
+    }
+
     /// Returns response body as a byte slice
     pub fn get_body(&self) -> &[u8] {
         &self.body
     }
+
+    /// Returns the value of the given parameter of the `Content-Type` header.
+    fn content_type_param(&self, name: &str) -> Option<&str> {
+        self["Content-Type"].as_ref().and_then(|content_type| {
+            content_type.split(';').skip(1).filter_map(|param| {
+                let mut parts = param.splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some(key), Some(value)) if key.trim().eq_ignore_ascii_case(name) => {
+                        Some(value.trim().trim_matches('"'))
+                    }
+                    _ => None,
+                }
+            }).next()
+        })
+    }
+
+    /// Returns the value of the `charset` parameter of the `Content-Type` header.
+    pub fn charset(&self) -> Option<&str> {
+        self.content_type_param("charset")
+    }
+
+    /// Returns the encoding of the body, given by the `charset` parameter of the
+    /// `Content-Type` header, or else by a byte order mark, or else `default`.
+    fn encoding(&self, default: &'static Encoding) -> &'static Encoding {
+        self.charset()
+            .and_then(|charset| Encoding::for_label(charset.as_bytes()))
+            .or_else(|| Encoding::for_bom(&self.body).map(|(encoding, _)| encoding))
+            .unwrap_or(default)
+    }
+
+    /// Decodes the body of this response as text, assuming UTF-8 if no encoding
+    /// is specified.
+    ///
+    /// Returns an error if the body is malformed in its encoding.
+    pub fn text(&self) -> Result<Cow<'_, str>, Error> {
+        self.text_with_default(UTF_8)
+    }
+
+    /// Decodes the body of this response as text, assuming the given encoding
+    /// if no encoding is specified.
+    ///
+    /// Returns an error if the body is malformed in its encoding.
+    pub fn text_with_default(&self, default: &'static Encoding) -> Result<Cow<'_, str>, Error> {
+        let encoding = self.encoding(default);
+        let body = match Encoding::for_bom(&self.body) {
+            Some((bom, len)) if bom == encoding => &self.body[len..],
+            _ => &self.body[..],
+        };
+        encoding
+            .decode_without_bom_handling_and_without_replacement(body)
+            .ok_or_else(|| {
+                Error::new(ErrorKind::InvalidData, format!("body is not valid {}", encoding.name()))
+            })
+    }
+
+    /// Decodes the body of this response as text, assuming UTF-8 if no encoding
+    /// is specified.
+    ///
+    /// Malformed sequences are replaced with the replacement character.
+    pub fn text_lossy(&self) -> Cow<'_, str> {
+        self.text_lossy_with_default(UTF_8)
+    }
+
+    /// Decodes the body of this response as text, assuming the given encoding
+    /// if no encoding is specified.
+    ///
+    /// Malformed sequences are replaced with the replacement character.
+    pub fn text_lossy_with_default(&self, default: &'static Encoding) -> Cow<'_, str> {
+        self.encoding(default).decode_with_bom_removal(&self.body).0
+    }
+
+    /// Deserializes the body of this response from JSON.
+    #[cfg(feature = "json")]
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, serde_json::Error> {
+        serde_json::from_slice(&self.body)
+    }
+}
+
+/// Returns the position of `needle` in `haystack`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
 }
 
 /// Appends data to this response's body.
+#[cfg(any(test, feature = "legacy", feature = "tokio1"))]
 pub fn append<A: AsRef<[u8]>>(res: &mut HttpResponse, buf: A) {
     res.body.extend_from_slice(buf.as_ref());
 }
 
+/// Removes the body of this response, and returns it.
+#[cfg(feature = "cassette")]
+pub fn take_body(res: &mut HttpResponse) -> Vec<u8> {
+    ::std::mem::take(&mut res.body)
+}
+
+/// Sets the timings of the exchange of this response.
+#[cfg(any(test, feature = "legacy", feature = "tokio1"))]
+pub fn set_timings(res: &mut HttpResponse, timings: Timings) {
+    res.timings = timings;
+}
+
+/// Returns a mutable reference to this response's headers.
+#[cfg(feature = "legacy")]
+pub fn headers_mut(res: &mut HttpResponse) -> &mut Vec<Header> {
+    &mut res.headers
+}
+
 const NONE: &'static Option<String> = &None;
 
+/// Number of characters of the body shown by `Display`.
+const PREVIEW_CHARS: usize = 30;
+
+/// Number of bytes decoded for the preview, enough for `PREVIEW_CHARS` characters
+/// of up to 4 bytes each and a BOM.
+const PREVIEW_BYTES: usize = PREVIEW_CHARS * 4 + 4;
+
 impl<'a> Index<&'a str> for HttpResponse {
     type Output = Option<String>;
 
@@ -146,9 +422,73 @@ impl fmt::Display for HttpResponse {
             writeln!(f, "{}", header)?;
         }
         write!(f, "body: {} bytes = [", self.body.len())?;
-        for byte in &self.body[0..cmp::min(self.body.len(), 30)] {
-            write!(f, "{}", *byte as char)?;
+        // only a prefix is decoded, whatever the size of the body
+        let prefix = &self.body[..self.body.len().min(PREVIEW_BYTES)];
+        let (text, _) = self.encoding(UTF_8).decode_with_bom_removal(prefix);
+        for c in text.chars().take(PREVIEW_CHARS) {
+            write!(f, "{}", c)?;
         }
         writeln!(f, "...]")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use encoding_rs::WINDOWS_1252;
+
+    use metrics::Recorder;
+    use super::{append, new_header, new_response, set_timings};
+
+    #[test]
+    fn equality() {
+        let res = new_response((1, 1), 200, vec![new_header("Server", "test")]);
+        let mut timed = res.clone();
+        let recorder = Recorder::new();
+        recorder.request_sent();
+        set_timings(&mut timed, recorder.timings());
+        assert_eq!(res, timed);
+
+        append(&mut timed, b"body");
+        assert_ne!(res, timed);
+    }
+
+    #[test]
+    fn text() {
+        let mut res = new_response((1, 1), 200, vec![
+            new_header("Content-Type", "text/plain; charset=\"ISO-8859-1\""),
+        ]);
+        append(&mut res, b"caf\xe9");
+        assert_eq!(res.charset(), Some("ISO-8859-1"));
+        assert_eq!(res.text().unwrap(), "café");
+
+        // byte order mark
+        let mut res = new_response((1, 1), 200, vec![new_header("Content-Type", "text/plain")]);
+        append(&mut res, b"\xff\xfeh\x00i\x00");
+        assert_eq!(res.charset(), None);
+        assert_eq!(res.text().unwrap(), "hi");
+
+        // default
+        let mut res = new_response((1, 1), 200, vec![]);
+        append(&mut res, b"na\xefve");
+        assert!(res.text().is_err());
+        assert_eq!(res.text_lossy(), "na\u{fffd}ve");
+        assert_eq!(res.text_with_default(WINDOWS_1252).unwrap(), "naïve");
+    }
+
+    #[test]
+    fn display() {
+        let mut res = new_response((1, 1), 200, vec![
+            new_header("Content-Type", "text/plain; charset=utf-8"),
+        ]);
+        append(&mut res, "ça va très bien, merci beaucoup !".as_bytes());
+        assert_eq!(
+            format!("{}", res),
+            "HTTP/1.1 200\nContent-Type: text/plain; charset=utf-8\n\
+             body: 35 bytes = [ça va très bien, merci beaucou...]\n"
+        );
+
+        let mut res = new_response((1, 1), 200, vec![]);
+        append(&mut res, vec![b'a'; 1 << 20]);
+        assert_eq!(format!("{}", res), format!("HTTP/1.1 200\nbody: 1048576 bytes = [{}...]\n", "a".repeat(30)));
+    }
+}
@@ -0,0 +1,331 @@
+//! Server side of HTTP/1.1: decoding of requests and encoding of responses.
+//!
+//! `ServerCodec` mirrors `HttpCodec`, so that small servers, proxies and test doubles
+//! can be written with the same `HttpRequest` and `HttpResponse` types:
+//!
+//! ```no-run
+//! let server = listener.incoming().for_each(|(io, _)| {
+//!     let (sink, stream) = io.framed(ServerCodec::new()).split();
+//!     let responses = stream.map(|req| HttpResponse::new(200).body(format!("{}", req.url())));
+//!     handle.spawn(sink.send_all(responses).then(|_| Ok(())));
+//!     Ok(())
+//! });
+//! ```
+
+use std::collections::VecDeque;
+use std::io::{self, Error, ErrorKind};
+
+use bytes::BytesMut;
+
+use nom::IResult;
+
+use tokio_io::codec::{Decoder, Encoder};
+
+use url::Url;
+
+use body::{Decoded, Framing};
+use parser::{self, RequestHead};
+use {HttpRequest, HttpResponse, Method};
+
+/// Returns the reason phrase of the given status code, or "Unknown".
+pub fn reason_phrase(status: u32) -> &'static str {
+    match status {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        412 => "Precondition Failed",
+        413 => "Payload Too Large",
+        416 => "Range Not Satisfiable",
+        417 => "Expectation Failed",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "Unknown",
+    }
+}
+
+fn parse_method(method: String) -> Method {
+    match method.as_str() {
+        "GET" => Method::Get,
+        "HEAD" => Method::Head,
+        "POST" => Method::Post,
+        "PUT" => Method::Put,
+        "DELETE" => Method::Delete,
+        "CONNECT" => Method::Connect,
+        "OPTIONS" => Method::Options,
+        "TRACE" => Method::Trace,
+        _ => Method::Other(method),
+    }
+}
+
+/// Returns the URL of a request, given its request-target and `Host` header.
+///
+/// The asterisk-form of `OPTIONS *` becomes the path `/*`.
+fn request_url(target: &str, host: Option<&str>) -> Result<Url, Error> {
+    let host = host.unwrap_or("localhost");
+    let url = if target.starts_with('/') {
+        format!("http://{}{}", host, target)
+    } else if target == "*" {
+        format!("http://{}/*", host)
+    } else if target.contains("://") {
+        target.to_string()
+    } else {
+        // authority-form of CONNECT
+        format!("http://{}/", target)
+    };
+    url.parse().map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+/// Codec that parses HTTP requests and encodes HTTP responses.
+///
+/// Request bodies are delimited by their `Content-Length` or the chunked transfer
+/// coding; requests without either have an empty body, and requests with both or
+/// with conflicting lengths are rejected. Responses are encoded in the
+/// order the requests were decoded, without a body if they answer a HEAD request, and
+/// with a `Content-Length` unless they have one or are chunked.
+pub struct ServerCodec {
+    request: Option<HttpRequest>,
+    framing: Framing,
+    /// Whether each request waiting for its response is a HEAD request.
+    heads: VecDeque<bool>,
+}
+
+impl Default for ServerCodec {
+    fn default() -> ServerCodec {
+        ServerCodec::new()
+    }
+}
+
+impl ServerCodec {
+    /// Creates a new server codec.
+    pub fn new() -> ServerCodec {
+        ServerCodec {
+            request: None,
+            framing: Framing::empty(),
+            heads: VecDeque::new(),
+        }
+    }
+
+    fn decode_header(&mut self, buf: &mut BytesMut) -> Result<Option<HttpRequest>, Error> {
+        let (bytes_left, head) = match parser::request(buf.as_ref()) {
+            IResult::Incomplete(_) => return Ok(None), // not enough data
+            IResult::Error(e) => return Err(Error::new(ErrorKind::InvalidData, e)),
+            IResult::Done(rest, head) => (rest.len(), head),
+        };
+
+        // eat parsed bytes
+        let after_header = buf.len() - bytes_left;
+        buf.split_to(after_header);
+
+        let RequestHead { method, target, version, headers } = head;
+        if version.0 != 1 {
+            return Err(Error::new(ErrorKind::InvalidData, "unsupported HTTP version"));
+        }
+        let headers = headers
+            .into_iter()
+            .map(|header| (header.name().to_string().into(), header.value().unwrap_or("").to_string().into()))
+            .collect();
+        let mut req = HttpRequest {
+            url: "http://localhost/".parse().unwrap(),
+            method: parse_method(method),
+            headers: headers,
+            body: vec![],
+            stream: None,
+            credentials: None,
+        };
+        req.url = request_url(&target, req.get_header("Host"))?;
+
+        self.framing = Framing::of_request(&req)?;
+        self.request = Some(req);
+        self.decode(buf)
+    }
+
+    fn complete(&mut self) -> Option<HttpRequest> {
+        let req = self.request.take();
+        if let Some(ref req) = req {
+            self.heads.push_back(req.method == Method::Head);
+        }
+        req
+    }
+}
+
+impl Decoder for ServerCodec {
+    type Item = HttpRequest;
+    type Error = Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<HttpRequest>, Error> {
+        if self.request.is_none() {
+            return self.decode_header(buf);
+        }
+        loop {
+            match self.framing.decode(buf)? {
+                Decoded::Data(data) => {
+                    if let Some(ref mut req) = self.request {
+                        req.body.extend_from_slice(&data);
+                    }
+                }
+                Decoded::Incomplete => return Ok(None), // not enough data
+                Decoded::End => return Ok(self.complete()),
+            }
+        }
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<HttpRequest>, Error> {
+        if let Some(req) = self.decode(buf)? {
+            return Ok(Some(req));
+        }
+        if buf.is_empty() && self.request.is_none() {
+            Ok(None)
+        } else {
+            Err(Error::new(ErrorKind::UnexpectedEof, "connection closed before end of request"))
+        }
+    }
+}
+
+impl Encoder for ServerCodec {
+    type Item = HttpResponse;
+    type Error = Error;
+
+    fn encode(&mut self, msg: HttpResponse, buf: &mut BytesMut) -> io::Result<()> {
+        // interim responses precede the final response to the same request
+        let head = if msg.is_informational() && msg.status() != 101 {
+            false
+        } else {
+            self.heads.pop_front().unwrap_or(false)
+        };
+
+        let (major, minor) = msg.version();
+        let status = msg.status();
+        buf.extend(format!("HTTP/{}.{} {} {}\r\n", major, minor, status, reason_phrase(status)).as_bytes());
+        for header in msg.headers() {
+            buf.extend(format!("{}\r\n", header).as_bytes());
+        }
+
+        let chunked = msg.has("Transfer-Encoding", "chunked");
+        let no_content = msg.is_informational() || status == 204 || status == 304;
+        if !no_content && !chunked && msg["Content-Length"].is_none() {
+            buf.extend(format!("Content-Length: {}\r\n", msg.get_body().len()).as_bytes());
+        }
+        buf.extend_from_slice(b"\r\n");
+        if head || no_content {
+            return Ok(());
+        }
+
+        let body = msg.get_body();
+        if chunked {
+            if !body.is_empty() {
+                buf.extend(format!("{:x}\r\n", body.len()).as_bytes());
+                buf.extend_from_slice(body);
+                buf.extend_from_slice(b"\r\n");
+            }
+            buf.extend_from_slice(b"0\r\n\r\n");
+        } else {
+            buf.extend_from_slice(body);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+
+    use bytes::BytesMut;
+
+    use tokio_io::codec::{Decoder, Encoder};
+
+    use {HttpCodec, HttpRequest, HttpResponse, Method};
+    use super::ServerCodec;
+
+    #[test]
+    fn decode_requests() {
+        let mut codec = ServerCodec::new();
+        let mut buf = BytesMut::new();
+        HttpCodec::new()
+            .encode(HttpRequest::post("http://localhost:3000/items?page=2", "item").unwrap(), &mut buf)
+            .unwrap();
+        buf.extend_from_slice(b"PATCH /a HTTP/1.1\r\nHost: example.com\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc");
+
+        let req = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(*req.method(), Method::Post);
+        assert_eq!(req.url().as_str(), "http://localhost:3000/items?page=2");
+        assert_eq!(req.get_header("host"), Some("localhost:3000"));
+        assert_eq!(req.get_body(), b"item");
+
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        buf.extend_from_slice(b"\r\n0\r\n\r\nGET http://proxied.com/b HTTP/1.1\r\n\r\n");
+        let req = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(*req.method(), Method::Other("PATCH".to_string()));
+        assert_eq!(req.get_body(), b"abc");
+
+        let req = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(req.url().as_str(), "http://proxied.com/b");
+        assert!(buf.is_empty());
+
+        buf.extend_from_slice(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nab");
+        assert_eq!(codec.decode_eof(&mut buf).err().map(|e| e.kind()), Some(ErrorKind::UnexpectedEof));
+
+        for head in &[
+            &b"GET / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n"[..],
+            &b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 3\r\n\r\n"[..],
+            &b"POST / HTTP/1.1\r\nContent-Length: 3\r\nContent-Length: 4\r\n\r\n"[..],
+            &b"POST / HTTP/1.1\r\nContent-Length: 3, 4\r\n\r\n"[..],
+        ] {
+            let mut buf = BytesMut::from(*head);
+            assert_eq!(ServerCodec::new().decode(&mut buf).err().map(|e| e.kind()), Some(ErrorKind::InvalidData));
+        }
+
+        let mut buf = BytesMut::from(&b"POST / HTTP/1.1\r\nContent-Length: 3\r\nContent-Length: 3, 3\r\n\r\nabc"[..]);
+        assert_eq!(ServerCodec::new().decode(&mut buf).unwrap().unwrap().get_body(), b"abc");
+    }
+
+    #[test]
+    fn encode_responses() {
+        let mut codec = ServerCodec::new();
+        let mut buf = BytesMut::from(&b"HEAD / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n"[..]);
+        for _ in 0..3 {
+            codec.decode(&mut buf).unwrap().unwrap();
+        }
+
+        let mut out = BytesMut::new();
+        codec.encode(HttpResponse::new(200).body("hello"), &mut out).unwrap();
+        assert_eq!(out, &b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n"[..]);
+
+        out.clear();
+        codec.encode(HttpResponse::new(100), &mut out).unwrap();
+        let res = HttpResponse::new(201).header("Transfer-Encoding", "chunked").body("hello");
+        codec.encode(res, &mut out).unwrap();
+        codec.encode(HttpResponse::new(404).header("Content-Type", "text/plain").body("missing"), &mut out).unwrap();
+        assert_eq!(
+            out,
+            &b"HTTP/1.1 100 Continue\r\n\r\n\
+               HTTP/1.1 201 Created\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n\
+               HTTP/1.1 404 Not Found\r\nContent-Type: text/plain\r\nContent-Length: 7\r\n\r\nmissing"[..]
+        );
+
+        let mut client = HttpCodec::new();
+        let res = client.decode(&mut out).unwrap().unwrap();
+        assert_eq!((res.status(), res.get_body()), (201, &b"hello"[..]));
+        let res = client.decode(&mut out).unwrap().unwrap();
+        assert_eq!((res.status(), res.get_body()), (404, &b"missing"[..]));
+    }
+}
@@ -0,0 +1,321 @@
+//! SOCKS5 proxy support.
+//!
+//! Implements the client side of RFC 1928 with the CONNECT command, and the
+//! username/password authentication method of RFC 1929. Host names are sent as-is
+//! to the proxy, which is then responsible for resolving them.
+
+use std::io::{Error, ErrorKind};
+use std::net::SocketAddr;
+
+use futures::{Future, future};
+
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::Handle;
+
+use tokio_io::{IoFuture, AsyncRead, AsyncWrite};
+use tokio_io::io::{read_exact, write_all};
+
+use url::{Url, Host};
+
+const VERSION: u8 = 5;
+
+const METHOD_NO_AUTH: u8 = 0;
+const METHOD_USER_PASS: u8 = 2;
+const METHOD_NONE_ACCEPTABLE: u8 = 0xff;
+
+const USER_PASS_VERSION: u8 = 1;
+
+const CMD_CONNECT: u8 = 1;
+
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+/// SOCKS5 proxy.
+#[derive(Clone, Debug)]
+pub struct Socks5 {
+    proxy: SocketAddr,
+    auth: Option<(String, String)>,
+}
+
+impl Socks5 {
+    /// Creates a new SOCKS5 proxy listening at the given address.
+    pub fn new(proxy: SocketAddr) -> Socks5 {
+        Socks5 {
+            proxy: proxy,
+            auth: None,
+        }
+    }
+
+    /// Authenticates with the given username and password (RFC 1929).
+    pub fn auth<U: Into<String>, P: Into<String>>(mut self, username: U, password: P) -> Socks5 {
+        self.auth = Some((username.into(), password.into()));
+        self
+    }
+
+    /// Returns the address of the proxy.
+    pub fn addr(&self) -> &SocketAddr {
+        &self.proxy
+    }
+
+    /// Returns a future that connects to the proxy, and asks it to open a
+    /// connection to the host and port of the given `url`.
+    pub fn connect(&self, url: &Url, handle: &Handle) -> IoFuture<TcpStream> {
+        debug!("connecting to {} through SOCKS5 proxy {}", ::redact_url(url), self.proxy);
+        let this = self.clone();
+        let url = url.clone();
+        Box::new(TcpStream::connect(&self.proxy, handle).and_then(move |io| {
+            this.handshake(io, &url)
+        }))
+    }
+
+    /// Returns a future that performs the SOCKS5 handshake over `io`, which must
+    /// be a connection to the proxy. Resolves to `io` once the proxy has connected
+    /// it to the host and port of the given `url`.
+    pub fn handshake<T>(&self, io: T, url: &Url) -> IoFuture<T>
+    where
+        T: 'static + AsyncRead + AsyncWrite + Send,
+    {
+        let request = match connect_request(url) {
+            Ok(request) => request,
+            Err(e) => return Box::new(future::err(e)),
+        };
+
+        let auth = self.auth.clone();
+        let greeting = if auth.is_some() {
+            vec![VERSION, 2, METHOD_NO_AUTH, METHOD_USER_PASS]
+        } else {
+            vec![VERSION, 1, METHOD_NO_AUTH]
+        };
+
+        Box::new(write_all(io, greeting)
+            .and_then(|(io, _)| read_exact(io, [0u8; 2]))
+            .and_then(move |(io, reply)| -> IoFuture<T> {
+                if reply[0] != VERSION {
+                    return Box::new(future::err(invalid_data("invalid SOCKS version")));
+                }
+
+                match (reply[1], auth) {
+                    (METHOD_NO_AUTH, _) => Box::new(future::ok(io)),
+                    (METHOD_USER_PASS, Some((username, password))) => {
+                        authenticate(io, &username, &password)
+                    }
+                    (METHOD_NONE_ACCEPTABLE, _) => Box::new(future::err(Error::new(
+                        ErrorKind::PermissionDenied,
+                        "no acceptable SOCKS authentication method",
+                    ))),
+                    _ => Box::new(future::err(invalid_data("unexpected SOCKS authentication method"))),
+                }
+            })
+            .and_then(|io| write_all(io, request))
+            .and_then(|(io, _)| read_exact(io, [0u8; 4]))
+            .and_then(|(io, reply)| -> IoFuture<T> {
+                if reply[0] != VERSION {
+                    return Box::new(future::err(invalid_data("invalid SOCKS version")));
+                }
+
+                if reply[1] != 0 {
+                    return Box::new(future::err(Error::other(reply_message(reply[1]))));
+                }
+
+                // skip bound address and port
+                match reply[3] {
+                    ATYP_IPV4 => Box::new(read_exact(io, [0u8; 4 + 2]).map(|(io, _)| io)),
+                    ATYP_IPV6 => Box::new(read_exact(io, [0u8; 16 + 2]).map(|(io, _)| io)),
+                    ATYP_DOMAIN => Box::new(read_exact(io, [0u8; 1]).and_then(|(io, len)| {
+                        read_exact(io, vec![0u8; len[0] as usize + 2]).map(|(io, _)| io)
+                    })),
+                    _ => Box::new(future::err(invalid_data("invalid SOCKS address type"))),
+                }
+            }))
+    }
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Performs username/password authentication as described in RFC 1929.
+fn authenticate<T>(io: T, username: &str, password: &str) -> IoFuture<T>
+where
+    T: 'static + AsyncRead + AsyncWrite + Send,
+{
+    if username.len() > 255 || password.len() > 255 {
+        return Box::new(future::err(Error::new(
+            ErrorKind::InvalidInput,
+            "SOCKS username and password must be at most 255 bytes long",
+        )));
+    }
+
+    let mut request = vec![USER_PASS_VERSION, username.len() as u8];
+    request.extend_from_slice(username.as_bytes());
+    request.push(password.len() as u8);
+    request.extend_from_slice(password.as_bytes());
+
+    Box::new(write_all(io, request)
+        .and_then(|(io, _)| read_exact(io, [0u8; 2]))
+        .and_then(|(io, reply)| if reply[0] != USER_PASS_VERSION {
+            Err(invalid_data("invalid SOCKS authentication version"))
+        } else if reply[1] == 0 {
+            Ok(io)
+        } else {
+            Err(Error::new(ErrorKind::PermissionDenied, "SOCKS authentication failed"))
+        }))
+}
+
+/// Builds the CONNECT request to the host and port of the given URL.
+fn connect_request(url: &Url) -> Result<Vec<u8>, Error> {
+    let port = url.port_or_known_default().ok_or_else(|| {
+        Error::new(ErrorKind::InvalidInput, "no port")
+    })?;
+
+    let mut request = vec![VERSION, CMD_CONNECT, 0];
+    match url.host() {
+        Some(Host::Domain(domain)) => {
+            if domain.len() > 255 {
+                return Err(Error::new(ErrorKind::InvalidInput, "host name too long"));
+            }
+            request.push(ATYP_DOMAIN);
+            request.push(domain.len() as u8);
+            request.extend_from_slice(domain.as_bytes());
+        }
+        Some(Host::Ipv4(addr)) => {
+            request.push(ATYP_IPV4);
+            request.extend_from_slice(&addr.octets());
+        }
+        Some(Host::Ipv6(addr)) => {
+            request.push(ATYP_IPV6);
+            request.extend_from_slice(&addr.octets());
+        }
+        None => return Err(Error::new(ErrorKind::InvalidInput, "no host")),
+    }
+    request.push((port >> 8) as u8);
+    request.push(port as u8);
+    Ok(request)
+}
+
+fn reply_message(code: u8) -> &'static str {
+    match code {
+        1 => "general SOCKS server failure",
+        2 => "connection not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown SOCKS error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+    use std::net::{TcpListener, SocketAddr};
+    use std::thread;
+
+    use url::Url;
+
+    use prelude::*;
+    use HttpRequest;
+    use super::Socks5;
+
+    /// Starts a SOCKS5 server that accepts a single connection, expects the given
+    /// credentials and the given CONNECT request, and replies with `reply`.
+    fn server(credentials: &'static [u8], target: &'static [u8], reply: u8) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+
+            let mut greeting = [0u8; 4];
+            stream.read_exact(&mut greeting).unwrap();
+            assert_eq!(greeting, [5, 2, 0, 2]);
+            stream.write_all(&[5, 2]).unwrap();
+
+            let mut auth = vec![0u8; credentials.len()];
+            stream.read_exact(&mut auth).unwrap();
+            assert_eq!(&auth[..], credentials);
+            stream.write_all(&[1, 0]).unwrap();
+
+            let mut request = vec![0u8; target.len()];
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(&request[..], target);
+            stream.write_all(&[5, reply, 0, 1, 127, 0, 0, 1, 0x1f, 0x90]).unwrap();
+            if reply != 0 {
+                return;
+            }
+
+            let mut head = vec![];
+            let mut byte = [0u8; 1];
+            while !head.ends_with(b"\r\n\r\n") {
+                stream.read_exact(&mut byte).unwrap();
+                head.push(byte[0]);
+            }
+            assert!(head.starts_with(b"GET /index.html HTTP/1.1\r\nHost: example.com\r\n"));
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello").unwrap();
+        });
+        addr
+    }
+
+    #[test]
+    fn connect() {
+        let addr = server(
+            b"\x01\x04user\x06secret",
+            b"\x05\x01\x00\x03\x0bexample.com\x00\x50",
+            0,
+        );
+
+        let url = Url::parse("http://example.com/index.html").unwrap();
+        let req = HttpRequest::get(url.as_str()).unwrap();
+        let proxy = Socks5::new(addr).auth("user", "secret");
+
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (res, _) = core.run(proxy.connect(&url, &handle).and_then(|io| req.send(io))).unwrap();
+        let res = res.unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.get_body(), b"hello");
+    }
+
+    #[test]
+    fn refused() {
+        let addr = server(
+            b"\x01\x04user\x06secret",
+            b"\x05\x01\x00\x01\x0a\x00\x00\x01\x1f\x90",
+            5,
+        );
+
+        let url = Url::parse("http://10.0.0.1:8080/").unwrap();
+        let proxy = Socks5::new(addr).auth("user", "secret");
+
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let err = core.run(proxy.connect(&url, &handle)).err().unwrap();
+        assert_eq!(err.to_string(), "connection refused");
+    }
+
+    #[test]
+    fn invalid_auth_version() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut greeting = [0u8; 4];
+            stream.read_exact(&mut greeting).unwrap();
+            stream.write_all(&[5, 2]).unwrap();
+            let mut auth = [0u8; 13];
+            stream.read_exact(&mut auth).unwrap();
+            // a SOCKS version instead of the subnegotiation version
+            stream.write_all(&[5, 0]).unwrap();
+        });
+
+        let url = Url::parse("http://example.com/").unwrap();
+        let proxy = Socks5::new(addr).auth("user", "secret");
+
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let err = core.run(proxy.connect(&url, &handle)).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,382 @@
+//! Server-Sent Events (`text/event-stream`) client.
+
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind};
+use std::mem;
+use std::time::Duration;
+
+use futures::{Async, Future, Poll, Stream};
+
+use bytes::BytesMut;
+
+use client::StreamingResponse;
+use {BodyStream, HttpRequest, HttpResponse};
+
+/// Event received from an event stream.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Event {
+    event: String,
+    data: String,
+    id: String,
+}
+
+impl Event {
+    /// Returns the type of this event, `message` unless specified by the server.
+    pub fn event(&self) -> &str {
+        &self.event
+    }
+
+    /// Returns the data of this event.
+    pub fn data(&self) -> &str {
+        &self.data
+    }
+
+    /// Returns the last event ID set by the server, or an empty string.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+/// Parser of the fields of an event stream.
+struct Parser {
+    buf: BytesMut,
+    started: bool,
+    after_cr: bool,
+    event: String,
+    data: String,
+    id: String,
+    last_event_id: String,
+    retry: Option<u64>,
+}
+
+impl Parser {
+    fn new(last_event_id: String) -> Parser {
+        Parser {
+            buf: BytesMut::new(),
+            started: false,
+            after_cr: false,
+            event: String::new(),
+            data: String::new(),
+            id: last_event_id.clone(),
+            last_event_id: last_event_id,
+            retry: None,
+        }
+    }
+
+    /// Parses the complete lines of `chunk` and of the data before it, and adds the
+    /// events dispatched to `events`.
+    fn feed(&mut self, chunk: &[u8], events: &mut VecDeque<Event>) {
+        self.buf.extend_from_slice(chunk);
+        loop {
+            // lines end with CRLF, LF or CR
+            if self.after_cr && !self.buf.is_empty() {
+                if self.buf[0] == b'\n' {
+                    self.buf.split_to(1);
+                }
+                self.after_cr = false;
+            }
+
+            let pos = match self.buf.iter().position(|&c| c == b'\r' || c == b'\n') {
+                Some(pos) => pos,
+                None => return,
+            };
+            let line = self.buf.split_to(pos + 1);
+            self.after_cr = line[pos] == b'\r';
+            let line = String::from_utf8_lossy(&line[..pos]).into_owned();
+            self.line(&line, events);
+        }
+    }
+
+    fn line(&mut self, line: &str, events: &mut VecDeque<Event>) {
+        let line = if self.started {
+            line
+        } else {
+            self.started = true;
+            line.trim_start_matches('\u{feff}')
+        };
+
+        if line.is_empty() {
+            return self.dispatch(events);
+        }
+        if line.starts_with(':') {
+            return; // comment
+        }
+
+        let (field, value) = match line.find(':') {
+            Some(pos) => {
+                let value = &line[pos + 1..];
+                (&line[..pos], value.strip_prefix(' ').unwrap_or(value))
+            }
+            None => (line, ""),
+        };
+        match field {
+            "event" => self.event = value.to_string(),
+            "data" => {
+                self.data.push_str(value);
+                self.data.push('\n');
+            }
+            "id" if !value.contains('\0') => self.id = value.to_string(),
+            "retry" if !value.is_empty() && value.bytes().all(|c| c.is_ascii_digit()) => {
+                self.retry = value.parse().ok()
+            }
+            _ => (),
+        }
+    }
+
+    fn dispatch(&mut self, events: &mut VecDeque<Event>) {
+        self.last_event_id = self.id.clone();
+        let event = mem::take(&mut self.event);
+        let mut data = mem::take(&mut self.data);
+        if data.is_empty() {
+            return;
+        }
+
+        data.pop();
+        events.push_back(Event {
+            event: if event.is_empty() { "message".to_string() } else { event },
+            data: data,
+            id: self.last_event_id.clone(),
+        });
+    }
+}
+
+enum State {
+    Connecting(StreamingResponse),
+    Open(BodyStream),
+    Waiting(Box<dyn Future<Item = (), Error = Error>>),
+    Closed,
+}
+
+/// Stream of the events sent by a server in a `text/event-stream` response.
+///
+/// When the connection is lost, the event source reconnects after the retry interval,
+/// which the server may change, and sends the ID of the last event received in a
+/// `Last-Event-ID` header. The stream ends when the server answers with 204 No Content,
+/// and fails on any other response that is not a 200 event stream.
+pub struct EventSource {
+    connect: Box<dyn FnMut(HttpRequest) -> StreamingResponse>,
+    sleep: Box<dyn FnMut(Duration) -> Box<dyn Future<Item = (), Error = Error>>>,
+    req: HttpRequest,
+    retry: Duration,
+    parser: Parser,
+    events: VecDeque<Event>,
+    state: State,
+}
+
+impl EventSource {
+    /// Creates an event source that connects with the given GET request.
+    ///
+    /// Connections are opened with `connect`, and `sleep` returns a future that waits
+    /// for the given duration before reconnecting, however long the server made the
+    /// retry interval. See `Client::event_source`.
+    pub fn new<F, S>(req: HttpRequest, connect: F, sleep: S) -> EventSource
+    where
+        F: 'static + FnMut(HttpRequest) -> StreamingResponse,
+        S: 'static + FnMut(Duration) -> Box<dyn Future<Item = (), Error = Error>>,
+    {
+        let req = req.set_header("Accept", "text/event-stream").set_header("Cache-Control", "no-cache");
+        let mut source = EventSource {
+            connect: Box::new(connect),
+            sleep: Box::new(sleep),
+            req: req,
+            retry: Duration::from_secs(3),
+            parser: Parser::new(String::new()),
+            events: VecDeque::new(),
+            state: State::Closed,
+        };
+        source.state = source.connect();
+        source
+    }
+
+    /// Returns the ID of the last event received, sent when reconnecting.
+    pub fn last_event_id(&self) -> &str {
+        &self.parser.last_event_id
+    }
+
+    /// Returns the current retry interval, three seconds unless set by the server.
+    pub fn retry(&self) -> Duration {
+        self.retry
+    }
+
+    fn connect(&mut self) -> State {
+        let req = match self.req.try_clone() {
+            Some(req) => req,
+            None => return State::Closed,
+        };
+        let req = if self.parser.last_event_id.is_empty() {
+            req
+        } else {
+            req.set_header("Last-Event-ID", self.parser.last_event_id.clone())
+        };
+        State::Connecting((self.connect)(req))
+    }
+
+    /// Checks that `res` is an event stream; returns false if the server asks to stop.
+    fn check(res: &HttpResponse) -> Result<bool, Error> {
+        if res.status() == 204 {
+            return Ok(false);
+        }
+
+        let is_event_stream = res["Content-Type"].as_ref().map_or(false, |content_type| {
+            let essence = content_type.split(';').next().unwrap_or("");
+            essence.trim().eq_ignore_ascii_case("text/event-stream")
+        });
+        if res.status() != 200 || !is_event_stream {
+            let msg = format!("not an event stream ({})", res.status());
+            return Err(Error::new(ErrorKind::InvalidData, msg));
+        }
+        Ok(true)
+    }
+}
+
+impl Stream for EventSource {
+    type Item = Event;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Event>, Error> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Ok(Async::Ready(Some(event)));
+            }
+
+            let next = match self.state {
+                State::Connecting(ref mut response) => match response.poll() {
+                    Ok(Async::Ready((res, body))) => match EventSource::check(&res) {
+                        Ok(true) => {
+                            let last_event_id = self.parser.last_event_id.clone();
+                            self.parser = Parser::new(last_event_id);
+                            State::Open(body)
+                        }
+                        Ok(false) => State::Closed,
+                        Err(e) => {
+                            self.state = State::Closed;
+                            return Err(e);
+                        }
+                    },
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(_) => State::Waiting((self.sleep)(self.retry)),
+                },
+                State::Open(ref mut body) => match body.poll() {
+                    Ok(Async::Ready(Some(chunk))) => {
+                        self.parser.feed(&chunk, &mut self.events);
+                        if let Some(retry) = self.parser.retry.take() {
+                            self.retry = Duration::from_millis(retry);
+                        }
+                        continue;
+                    }
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    // an event that is not complete is discarded
+                    Ok(Async::Ready(None)) | Err(_) => State::Waiting((self.sleep)(self.retry)),
+                },
+                State::Waiting(ref mut sleep) => match sleep.poll()? {
+                    Async::Ready(()) => self.connect(),
+                    Async::NotReady => return Ok(Async::NotReady),
+                },
+                State::Closed => return Ok(Async::Ready(None)),
+            };
+            self.state = next;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use futures::{Future, Stream};
+    use futures::future::Either;
+
+    use mock::MockConnector;
+    use timer;
+    use {Client, HttpRequest};
+    use super::{Event, Parser};
+
+    fn parse(chunks: &[&str]) -> Vec<Event> {
+        let mut parser = Parser::new(String::new());
+        let mut events = VecDeque::new();
+        for chunk in chunks {
+            parser.feed(chunk.as_bytes(), &mut events);
+        }
+        events.into_iter().collect()
+    }
+
+    fn event(event: &str, data: &str, id: &str) -> Event {
+        Event {
+            event: event.to_string(),
+            data: data.to_string(),
+            id: id.to_string(),
+        }
+    }
+
+    #[test]
+    fn parser() {
+        assert_eq!(
+            parse(&["\u{feff}: comment\ndata: YHOO\ndata: +2\ndata\r\n\r", "\nevent: add\ndata:73857293\r\rid", ": 1\ndata"]),
+            vec![event("message", "YHOO\n+2\n", ""), event("add", "73857293", "")]
+        );
+
+        // the ID is kept by later events, and an empty event only updates it
+        assert_eq!(
+            parse(&["id: 1\ndata: a\n\nid: 2\n\ndata: b\n\nid\ndata: c\n\n"]),
+            vec![event("message", "a", "1"), event("message", "b", "2"), event("message", "c", "")]
+        );
+
+        let mut parser = Parser::new(String::new());
+        parser.feed(b"retry: 1500\nretry: 2s\n", &mut VecDeque::new());
+        assert_eq!(parser.retry, Some(1500));
+    }
+
+    #[test]
+    fn event_source() {
+        let output = Arc::new(Mutex::new(vec![]));
+        let client = Client::with_connector(MockConnector::new(
+            vec![
+                b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\r\n\
+                  retry: 10\nid: 1\nevent: first\ndata: a\n\ndata: lost",
+                b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream; charset=utf-8\r\n\
+                  Transfer-Encoding: chunked\r\n\r\n9\r\ndata: b\n\n\r\n0\r\n\r\n",
+                b"HTTP/1.1 204 No Content\r\n\r\n",
+            ],
+            &output,
+        ));
+
+        let source = client.event_source(HttpRequest::get("http://example.com/events").unwrap());
+        let events = source.collect().wait().unwrap();
+        assert_eq!(events, vec![event("first", "a", "1"), event("message", "b", "1")]);
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        let requests = output.split("GET").skip(1).collect::<Vec<_>>();
+        assert_eq!(requests.len(), 3);
+        assert!(requests[0].contains("\r\nAccept: text/event-stream\r\n"));
+        assert!(!requests[0].contains("Last-Event-ID"));
+        assert!(requests[1].contains("\r\nLast-Event-ID: 1\r\n"));
+
+        let client = Client::with_connector(MockConnector::new(
+            vec![b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 0\r\n\r\n"],
+            &Arc::new(Mutex::new(vec![])),
+        ));
+        let mut source = client.event_source(HttpRequest::get("http://example.com/events").unwrap());
+        assert_eq!(source.retry(), Duration::from_secs(3));
+        assert!(source.by_ref().collect().wait().is_err());
+    }
+
+    #[test]
+    fn long_retry() {
+        let client = Client::with_connector(MockConnector::new(
+            vec![b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\r\nretry: 600000\ndata: a\n\n"],
+            &Arc::new(Mutex::new(vec![])),
+        ));
+        let source = client.event_source(HttpRequest::get("http://example.com/events").unwrap());
+        let (event, source) = source.into_future().wait().map_err(|(e, _)| e).unwrap();
+        assert_eq!(event.unwrap().data(), "a");
+        assert_eq!(source.retry(), Duration::from_secs(600));
+
+        // waiting ten minutes before reconnecting neither fails nor ends the stream
+        match source.into_future().select2(timer::sleep(Duration::from_millis(50))).wait() {
+            Ok(Either::B(_)) => (),
+            _ => panic!("event source did not wait"),
+        }
+    }
+}
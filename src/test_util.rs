@@ -0,0 +1,556 @@
+//! In-process HTTP/1.1 server for tests (test-util feature).
+//!
+//! The server runs on a background reactor thread, listens on an ephemeral port of
+//! the loopback interface, and answers requests with the response of the first
+//! expectation they match:
+//!
+//! ```no-run
+//! let server = MockServer::start().unwrap();
+//! server.expect(
+//!     Expectation::post("/items")
+//!         .header("Content-Type", "text/plain")
+//!         .body("item")
+//!         .respond(MockResponse::new(201).body("created")),
+//! );
+//!
+//! let res = core.run(client.request(HttpRequest::post(server.url("/items"), "item")?...));
+//! server.verify();
+//! ```
+//!
+//! Requests that match no expectation are answered with 404 Not Found, and make
+//! `verify` panic. Connections are kept alive unless a response closes them.
+
+use std::io::Error;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc as std_mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use futures::{Async, Future, Poll, Stream, future};
+use futures::future::{Either, Loop};
+use futures::sync::oneshot;
+
+use bytes::BytesMut;
+
+use tokio_core::net::{TcpListener, TcpStream};
+use tokio_core::reactor::{Core, Handle, Timeout};
+
+use tokio_io::AsyncRead;
+use tokio_io::codec::Decoder;
+use tokio_io::io::{shutdown, write_all};
+
+use url::Position;
+
+use HttpRequest;
+use server::{reason_phrase, ServerCodec};
+
+/// Request received by a `MockServer`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Request {
+    method: String,
+    target: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    /// Returns the method of this request.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    /// Returns the request-target of this request, i.e. its path and query.
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// Returns the headers of this request.
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    /// Returns the value of the first header with the given name.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|header| header.0.eq_ignore_ascii_case(name))
+            .map(|header| header.1.as_str())
+    }
+
+    /// Returns the body of this request, decoded if it was chunked.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Returns true if the connection must be closed after the response to this request.
+    fn closes(&self) -> bool {
+        self.header("Connection").map_or(false, |value| value.eq_ignore_ascii_case("close"))
+    }
+}
+
+/// How the body of a canned response is delimited.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Framing {
+    Length,
+    Chunked(usize),
+    Close,
+}
+
+/// Canned response of a `MockServer`.
+#[derive(Clone, Debug)]
+pub struct MockResponse {
+    status: u32,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    framing: Framing,
+    close: bool,
+    delay: Option<Duration>,
+    raw: Option<Vec<u8>>,
+}
+
+impl MockResponse {
+    /// Creates a response with the given status, and an empty body with a `Content-Length`.
+    pub fn new(status: u32) -> MockResponse {
+        MockResponse {
+            status: status,
+            headers: vec![],
+            body: vec![],
+            framing: Framing::Length,
+            close: false,
+            delay: None,
+            raw: None,
+        }
+    }
+
+    /// Creates a response that is sent as is, e.g. a malformed one, after which the
+    /// connection is closed.
+    pub fn raw<B: Into<Vec<u8>>>(bytes: B) -> MockResponse {
+        let mut response = MockResponse::new(0);
+        response.raw = Some(bytes.into());
+        response
+    }
+
+    /// Adds a header to this response.
+    pub fn header<K: Into<String>, V: Into<String>>(mut self, name: K, value: V) -> MockResponse {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Sets the body of this response.
+    pub fn body<B: Into<Vec<u8>>>(mut self, body: B) -> MockResponse {
+        self.body = body.into();
+        self
+    }
+
+    /// Sends the body with the chunked transfer coding, in chunks of at most `size` bytes.
+    pub fn chunked(mut self, size: usize) -> MockResponse {
+        self.framing = Framing::Chunked(size);
+        self
+    }
+
+    /// Sends the body without a `Content-Length`, and ends it by closing the connection.
+    pub fn close_delimited(mut self) -> MockResponse {
+        self.framing = Framing::Close;
+        self
+    }
+
+    /// Closes the connection after this response, with a `Connection: close` header.
+    pub fn close(mut self) -> MockResponse {
+        self.close = true;
+        self.header("Connection", "close")
+    }
+
+    /// Waits for the given duration before sending this response.
+    pub fn delay(mut self, delay: Duration) -> MockResponse {
+        self.delay = Some(delay);
+        self
+    }
+
+    /// Returns true if the connection is closed after this response.
+    fn closes(&self) -> bool {
+        self.close || self.framing == Framing::Close || self.raw.is_some()
+    }
+
+    /// Returns the bytes of this response, without its body if it answers a HEAD request.
+    fn to_bytes(&self, head: bool) -> Vec<u8> {
+        if let Some(ref raw) = self.raw {
+            return raw.clone();
+        }
+
+        let mut bytes = format!("HTTP/1.1 {} {}\r\n", self.status, reason_phrase(self.status)).into_bytes();
+        for &(ref name, ref value) in &self.headers {
+            bytes.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+        }
+        let no_content = self.status / 100 == 1 || self.status == 204 || self.status == 304;
+        match self.framing {
+            Framing::Length if !no_content => {
+                bytes.extend_from_slice(format!("Content-Length: {}\r\n", self.body.len()).as_bytes())
+            }
+            Framing::Chunked(_) => bytes.extend_from_slice(b"Transfer-Encoding: chunked\r\n"),
+            _ => (),
+        }
+        bytes.extend_from_slice(b"\r\n");
+        if head || no_content {
+            return bytes;
+        }
+
+        match self.framing {
+            Framing::Chunked(size) => {
+                for chunk in self.body.chunks(size.max(1)) {
+                    bytes.extend_from_slice(format!("{:x}\r\n", chunk.len()).as_bytes());
+                    bytes.extend_from_slice(chunk);
+                    bytes.extend_from_slice(b"\r\n");
+                }
+                bytes.extend_from_slice(b"0\r\n\r\n");
+            }
+            _ => bytes.extend_from_slice(&self.body),
+        }
+        bytes
+    }
+}
+
+/// Expected request, and the response to answer it with.
+#[derive(Clone, Debug)]
+pub struct Expectation {
+    method: String,
+    target: String,
+    headers: Vec<(String, String)>,
+    body: Option<Vec<u8>>,
+    times: Option<usize>,
+    hits: usize,
+    response: MockResponse,
+}
+
+impl Expectation {
+    /// Expects requests with the given method and request-target, e.g. `/items?page=2`,
+    /// and answers them with 200 OK until `respond` is called.
+    pub fn new<M: Into<String>, T: Into<String>>(method: M, target: T) -> Expectation {
+        Expectation {
+            method: method.into(),
+            target: target.into(),
+            headers: vec![],
+            body: None,
+            times: None,
+            hits: 0,
+            response: MockResponse::new(200),
+        }
+    }
+
+    /// Expects GET requests with the given request-target.
+    pub fn get<T: Into<String>>(target: T) -> Expectation {
+        Expectation::new("GET", target)
+    }
+
+    /// Expects POST requests with the given request-target.
+    pub fn post<T: Into<String>>(target: T) -> Expectation {
+        Expectation::new("POST", target)
+    }
+
+    /// Only matches requests that have a header with the given name and value.
+    pub fn header<K: Into<String>, V: Into<String>>(mut self, name: K, value: V) -> Expectation {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Only matches requests with the given body.
+    pub fn body<B: Into<Vec<u8>>>(mut self, body: B) -> Expectation {
+        self.body = Some(body.into());
+        self
+    }
+
+    /// Expects exactly `times` matching requests, instead of at least one.
+    pub fn times(mut self, times: usize) -> Expectation {
+        self.times = Some(times);
+        self
+    }
+
+    /// Answers matching requests with the given response.
+    pub fn respond(mut self, response: MockResponse) -> Expectation {
+        self.response = response;
+        self
+    }
+
+    fn matches(&self, req: &Request) -> bool {
+        self.times.map_or(true, |times| self.hits < times)
+            && self.method.eq_ignore_ascii_case(&req.method)
+            && self.target == req.target
+            && self.headers.iter().all(|&(ref name, ref value)| req.header(name) == Some(value.as_str()))
+            && self.body.as_ref().map_or(true, |body| *body == req.body)
+    }
+
+    fn is_satisfied(&self) -> bool {
+        match self.times {
+            Some(times) => self.hits == times,
+            None => self.hits > 0,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    expectations: Vec<Expectation>,
+    received: Vec<Request>,
+    unexpected: Vec<Request>,
+}
+
+impl State {
+    /// Records `req`, and returns the response to answer it with.
+    fn respond(&mut self, req: Request) -> MockResponse {
+        self.received.push(req.clone());
+        if let Some(expectation) = self.expectations.iter_mut().find(|expectation| expectation.matches(&req)) {
+            expectation.hits += 1;
+            return expectation.response.clone();
+        }
+
+        let msg = format!("no expectation matches {} {}", req.method, req.target);
+        self.unexpected.push(req);
+        MockResponse::new(404).body(msg)
+    }
+}
+
+/// HTTP/1.1 server for tests, which stops when dropped.
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MockServer {
+    /// Starts a server on an ephemeral port of the loopback interface.
+    pub fn start() -> Result<MockServer, Error> {
+        let state = Arc::new(Mutex::new(State::default()));
+        let server_state = state.clone();
+        let (ready, started) = std_mpsc::channel();
+        let (shutdown, stop) = oneshot::channel::<()>();
+        let thread = thread::Builder::new().name("mock-server".to_string()).spawn(move || {
+            let mut core = match Core::new() {
+                Ok(core) => core,
+                Err(e) => return ready.send(Err(e)).unwrap(),
+            };
+            let handle = core.handle();
+            let listener = match TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &handle) {
+                Ok(listener) => listener,
+                Err(e) => return ready.send(Err(e)).unwrap(),
+            };
+            ready.send(listener.local_addr()).unwrap();
+
+            let server = listener.incoming().for_each(move |(io, _)| {
+                handle.spawn(serve(io, server_state.clone(), handle.clone()).map_err(|_| ()));
+                Ok(())
+            });
+            // runs until the server is dropped
+            let _ = core.run(server.map_err(|_| ()).select(stop.map_err(|_| ())));
+        })?;
+
+        let addr = started
+            .recv()
+            .map_err(|_| Error::other("mock server thread panicked"))??;
+        Ok(MockServer {
+            addr: addr,
+            state: state,
+            shutdown: Some(shutdown),
+            thread: Some(thread),
+        })
+    }
+
+    /// Returns the address of this server.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Returns the URL of the given request-target on this server.
+    pub fn url(&self, target: &str) -> String {
+        format!("http://{}{}", self.addr, target)
+    }
+
+    /// Adds an expectation, which is tried after the ones added before it.
+    pub fn expect(&self, expectation: Expectation) {
+        self.state.lock().unwrap().expectations.push(expectation);
+    }
+
+    /// Returns the requests received so far.
+    pub fn received(&self) -> Vec<Request> {
+        self.state.lock().unwrap().received.clone()
+    }
+
+    /// Panics if a request matched no expectation, or if an expectation was not met.
+    pub fn verify(&self) {
+        let state = self.state.lock().unwrap();
+        if let Some(req) = state.unexpected.first() {
+            panic!("unexpected request {} {}", req.method, req.target);
+        }
+        for expectation in &state.expectations {
+            if !expectation.is_satisfied() {
+                panic!(
+                    "expected {} {} {} times, received {}",
+                    expectation.method,
+                    expectation.target,
+                    expectation.times.unwrap_or(1),
+                    expectation.hits
+                );
+            }
+        }
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        // dropping the sender stops the reactor thread
+        self.shutdown.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+type Step = Loop<(), (TcpStream, BytesMut)>;
+
+/// Answers the requests received on `io` until it is closed.
+fn serve(io: TcpStream, state: Arc<Mutex<State>>, handle: Handle) -> Box<dyn Future<Item = (), Error = Error>> {
+    Box::new(future::loop_fn((io, BytesMut::new()), move |(io, buf)| {
+        let (state, handle) = (state.clone(), handle.clone());
+        ReadRequest { io: Some(io), buf: buf, codec: ServerCodec::new() }.and_then(move |(io, buf, req)| -> Box<dyn Future<Item = Step, Error = Error>> {
+            let req = match req {
+                Some(req) => req,
+                None => return Box::new(future::ok(Loop::Break(()))),
+            };
+
+            let (head, close) = (req.method == "HEAD", req.closes());
+            let response = state.lock().unwrap().respond(req);
+            let bytes = response.to_bytes(head);
+            let close = close || response.closes();
+            let delay = match response.delay {
+                Some(delay) => match Timeout::new(delay, &handle) {
+                    Ok(timeout) => Either::A(timeout),
+                    Err(e) => return Box::new(future::err(e)),
+                },
+                None => Either::B(future::ok(())),
+            };
+
+            Box::new(delay.and_then(move |_| write_all(io, bytes)).and_then(move |(io, _)| if close {
+                Either::A(shutdown(io).map(|_| Loop::Break(())))
+            } else {
+                Either::B(future::ok(Loop::Continue((io, buf))))
+            }))
+        })
+    }))
+}
+
+/// Future that reads a request, and resolves to the connection, the data read past the
+/// request and the request, or `None` if the connection was closed before one.
+struct ReadRequest {
+    io: Option<TcpStream>,
+    buf: BytesMut,
+    codec: ServerCodec,
+}
+
+impl Future for ReadRequest {
+    type Item = (TcpStream, BytesMut, Option<Request>);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Error> {
+        loop {
+            if let Some(req) = self.codec.decode(&mut self.buf)? {
+                let io = self.io.take().expect("poll after completion");
+                return Ok(Async::Ready((io, self.buf.take(), Some(received(req)))));
+            }
+
+            self.buf.reserve(8192);
+            let read = self.io.as_mut().expect("poll after completion").read_buf(&mut self.buf)?;
+            match read {
+                Async::Ready(0) => {
+                    let req = self.codec.decode_eof(&mut self.buf)?;
+                    let io = self.io.take().expect("poll after completion");
+                    return Ok(Async::Ready((io, BytesMut::new(), req.map(received))));
+                }
+                Async::Ready(_) => (),
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+/// Returns the summary of a request decoded by a `ServerCodec`.
+fn received(req: HttpRequest) -> Request {
+    Request {
+        method: req.method().to_string(),
+        target: req.url()[Position::BeforePath..].to_string(),
+        headers: req.headers().iter().map(|&(ref name, ref value)| (name.to_string(), value.to_string())).collect(),
+        body: req.get_body().to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+    use std::time::{Duration, Instant};
+
+    use futures::{Stream, stream};
+
+    use bytes::Bytes;
+
+    use tokio_core::reactor::Core;
+
+    use {Client, HttpRequest};
+    use super::{Expectation, MockResponse, MockServer};
+
+    #[test]
+    fn mock_server() {
+        let server = MockServer::start().unwrap();
+        server.expect(
+            Expectation::post("/items")
+                .header("Content-Type", "text/plain")
+                .body("one two")
+                .respond(MockResponse::new(201).body("created").chunked(3)),
+        );
+        server.expect(Expectation::get("/close").respond(MockResponse::new(200).body("until close").close_delimited()));
+        server.expect(
+            Expectation::get("/slow")
+                .times(1)
+                .respond(MockResponse::new(200).delay(Duration::from_millis(50))),
+        );
+        server.expect(Expectation::get("/malformed").respond(MockResponse::raw("HTTP/1.1 bad\r\n\r\n")));
+
+        let mut core = Core::new().unwrap();
+        let client = Client::new(&core.handle());
+
+        let chunks = stream::iter_ok::<_, ::std::io::Error>(vec![Bytes::from("one "), Bytes::from("two")]);
+        let req = HttpRequest::post(server.url("/items"), vec![])
+            .unwrap()
+            .header("Content-Type", "text/plain")
+            .body_stream(chunks, None);
+        let res = core.run(client.request(req)).unwrap();
+        assert_eq!((res.status(), res.get_body()), (201, &b"created"[..]));
+
+        let res = core.run(client.get(server.url("/close"))).unwrap();
+        assert_eq!(res.get_body(), b"until close");
+
+        let start = Instant::now();
+        assert_eq!(core.run(client.get(server.url("/slow"))).unwrap().status(), 200);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        let err = core.run(client.get(server.url("/malformed"))).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        server.verify();
+
+        assert_eq!(core.run(client.get(server.url("/slow"))).unwrap().status(), 404);
+        assert_eq!(server.received().len(), 5);
+        assert_eq!(server.received()[0].body(), b"one two");
+
+        let (res, body) = core.run(client.request_streaming(HttpRequest::get(server.url("/close")).unwrap())).unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(core.run(body.concat2()).unwrap(), &b"until close"[..]);
+    }
+
+    #[test]
+    #[should_panic(expected = "unexpected request GET /missing")]
+    fn verify() {
+        let server = MockServer::start().unwrap();
+        let mut core = Core::new().unwrap();
+        let client = Client::new(&core.handle());
+        assert_eq!(core.run(client.get(server.url("/missing"))).unwrap().status(), 404);
+        server.verify();
+    }
+}
@@ -0,0 +1,75 @@
+//! Timer shared by all the clients of a process.
+
+use std::cmp;
+use std::io::Error;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use futures::{Future, future};
+use futures::future::Loop;
+
+use tokio_timer::{self, Timer};
+
+/// Resolution of the timer.
+const TICK: Duration = Duration::from_millis(10);
+
+/// Longest sleep of the timer; longer delays are split in several sleeps.
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+/// Future that resolves after a delay.
+pub type Sleep = Box<dyn Future<Item = (), Error = Error> + Send>;
+
+/// Returns the timer, whose thread is started on first use.
+fn timer() -> &'static Timer {
+    static TIMER: OnceLock<Timer> = OnceLock::new();
+    TIMER.get_or_init(|| {
+        tokio_timer::wheel()
+            .tick_duration(TICK)
+            .num_slots(8192)
+            .thread_name("async-http-client-timer")
+            .build()
+    })
+}
+
+/// Returns a future that resolves after `delay`, however long it is.
+///
+/// The timer is not started until the future is polled.
+pub fn sleep(delay: Duration) -> Sleep {
+    Box::new(future::lazy(move || {
+        // a deadline too far to be represented is never reached
+        let deadline = match Instant::now().checked_add(delay) {
+            Some(deadline) => deadline,
+            None => return future::Either::A(future::empty()),
+        };
+        future::Either::B(future::loop_fn(deadline, |deadline| {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left == Duration::from_millis(0) {
+                return future::Either::A(future::ok(Loop::Break(())));
+            }
+            let step = cmp::min(left, MAX_SLEEP);
+            future::Either::B(timer().sleep(step).map(move |_| Loop::Continue(deadline)).map_err(Error::from))
+        }))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use futures::Future;
+
+    use super::sleep;
+
+    #[test]
+    fn short_sleeps() {
+        let start = Instant::now();
+        sleep(Duration::from_millis(20)).wait().unwrap();
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(20) && elapsed < Duration::from_millis(100), "{:?}", elapsed);
+
+        // sleeps longer than the timer allows do not fail
+        let long = sleep(Duration::from_secs(3600)).select(sleep(Duration::from_millis(1)));
+        assert!(long.wait().is_ok());
+        assert!(sleep(Duration::from_secs(u64::MAX)).select(sleep(Duration::from_millis(1))).wait().is_ok());
+    }
+}
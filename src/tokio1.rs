@@ -0,0 +1,414 @@
+//! Native `std::future` API over tokio 1.x (tokio1 feature).
+//!
+//! `HttpRequest::send` is a `std::future::Future` over tokio 1.x connections,
+//! `HttpCodec` implements the codec traits of tokio-util, and `Client` sends requests
+//! over connections opened by its connector. Timeouts use the timer of the tokio
+//! runtime, without the reactor of tokio-core or the thread of tokio-timer:
+//!
+//! ```no-run
+//! let (res, io) = req.send(io).await?;
+//!
+//! let client = Client::new().timeout(Duration::from_secs(30));
+//! let res = client.get("http://example.com/").await?;
+//! ```
+//!
+//! Streamed request bodies and the other features of the futures 0.1 `Client` need the
+//! legacy feature.
+
+use std::future::Future;
+use std::io::{self, Error, ErrorKind};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use bytes1::{Buf, BytesMut};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::time::{self, Sleep};
+
+use tokio_util::codec::{Decoder, Encoder};
+use tokio_util::io::poll_read_buf;
+
+use url::Url;
+
+use metrics::Recorder;
+use response;
+use {Exchange, HttpCodec, HttpRequest, HttpResponse, SendRequest};
+
+impl Decoder for HttpCodec {
+    type Item = HttpResponse;
+    type Error = Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<HttpResponse>, Error> {
+        self.decode_from(buf)
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<HttpResponse>, Error> {
+        self.decode_eof_from(buf)
+    }
+}
+
+impl Encoder<HttpRequest> for HttpCodec {
+    type Error = Error;
+
+    fn encode(&mut self, msg: HttpRequest, buf: &mut BytesMut) -> io::Result<()> {
+        buf.extend_from_slice(self.encode_head(&msg).as_bytes());
+        buf.extend_from_slice(&msg.body);
+        Ok(())
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Future for SendRequest<T> {
+    type Output = io::Result<(Option<HttpResponse>, T)>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if let Some((req, io)) = this.pending.take() {
+            if req.is_streamed() {
+                return Poll::Ready(Err(Error::new(ErrorKind::InvalidInput, "streamed bodies need the legacy feature")));
+            }
+            trace!("sending {} {}{}", req.method, ::redact_url(&req.url), req.redacted_headers());
+            let mut codec = HttpCodec::new();
+            let mut write = BytesMut::new();
+            codec.encode(req, &mut write)?;
+            this.exchange = Some(Exchange {
+                io: io,
+                codec: codec,
+                write: write,
+                flushed: false,
+                read: BytesMut::new(),
+            });
+        }
+
+        let exchange = this.exchange.as_mut().expect("response received");
+        while !exchange.write.is_empty() {
+            let len = ready!(Pin::new(&mut exchange.io).poll_write(cx, &exchange.write))?;
+            if len == 0 {
+                return Poll::Ready(Err(Error::new(ErrorKind::WriteZero, "connection closed while sending request")));
+            }
+            exchange.write.advance(len);
+        }
+        if !exchange.flushed {
+            ready!(Pin::new(&mut exchange.io).poll_flush(cx))?;
+            exchange.flushed = true;
+        }
+
+        let res = loop {
+            if let Some(res) = exchange.codec.decode(&mut exchange.read)? {
+                break Some(res);
+            }
+            exchange.read.reserve(8192);
+            if ready!(poll_read_buf(Pin::new(&mut exchange.io), cx, &mut exchange.read))? == 0 {
+                break exchange.codec.decode_eof(&mut exchange.read)?;
+            }
+        };
+        let io = this.exchange.take().unwrap().io;
+        Poll::Ready(Ok((res, io)))
+    }
+}
+
+/// Future that resolves to a connection.
+pub type ConnectFuture<T> = Pin<Box<dyn Future<Output = io::Result<T>> + Send>>;
+
+/// A connector opens a tokio 1.x connection suitable for sending requests to a given URL.
+pub trait Connect {
+    /// Type of the connection.
+    type Io: 'static + AsyncRead + AsyncWrite + Unpin + Send;
+
+    /// Returns a future that resolves to a connection to the host of the given `url`.
+    fn connect(&self, url: &Url) -> ConnectFuture<Self::Io>;
+}
+
+/// Connector that opens plain TCP connections with tokio 1.x.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HttpConnector;
+
+impl Connect for HttpConnector {
+    type Io = TcpStream;
+
+    fn connect(&self, url: &Url) -> ConnectFuture<TcpStream> {
+        let addr = match (url.host_str(), url.port_or_known_default()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            _ => return Box::pin(async_err(Error::new(ErrorKind::InvalidInput, "no address"))),
+        };
+        debug!("connecting to {}", addr);
+        Box::pin(TcpStream::connect(addr))
+    }
+}
+
+/// Returns a future that fails with `e`.
+fn async_err<T>(e: Error) -> ::std::future::Ready<io::Result<T>> {
+    ::std::future::ready(Err(e))
+}
+
+/// HTTP client that sends each request over a connection opened by its connector, as
+/// `std::future::Future`s to run on a tokio 1.x runtime.
+pub struct Client<C = HttpConnector> {
+    connector: Arc<C>,
+    timeout: Option<Duration>,
+}
+
+impl<C> Clone for Client<C> {
+    fn clone(&self) -> Client<C> {
+        Client {
+            connector: self.connector.clone(),
+            timeout: self.timeout,
+        }
+    }
+}
+
+impl Client<HttpConnector> {
+    /// Creates a new client that uses plain TCP connections.
+    pub fn new() -> Client {
+        Client::with_connector(HttpConnector)
+    }
+}
+
+impl Default for Client<HttpConnector> {
+    fn default() -> Client {
+        Client::new()
+    }
+}
+
+impl<C: Connect> Client<C> {
+    /// Creates a new client that uses the given connector.
+    pub fn with_connector(connector: C) -> Client<C> {
+        Client {
+            connector: Arc::new(connector),
+            timeout: None,
+        }
+    }
+
+    /// Sets the time after which a request fails with `TimedOut`, from its connection
+    /// to the end of its response.
+    ///
+    /// The timer of the runtime must be enabled.
+    pub fn timeout(mut self, timeout: Duration) -> Client<C> {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Returns the connector of this client.
+    pub fn connector(&self) -> &C {
+        &self.connector
+    }
+
+    /// Returns a future that resolves to the response to a GET request to the given URL.
+    pub fn get<U: AsRef<str>>(&self, url: U) -> ResponseFuture<C> {
+        match HttpRequest::get(url) {
+            Ok(req) => self.request(req),
+            Err(e) => self.fail(Error::new(ErrorKind::InvalidInput, e)),
+        }
+    }
+
+    /// Returns a future that resolves to the response to the given request.
+    ///
+    /// A request with credentials is sent again if the server answers 401 with a
+    /// Digest challenge.
+    pub fn request(&self, req: HttpRequest) -> ResponseFuture<C> {
+        let retry = if req.credentials.is_some() {
+            req.try_clone()
+        } else {
+            None
+        };
+        let connection = self.connector.connect(&req.url);
+        ResponseFuture {
+            state: State::Connecting(connection),
+            connector: self.connector.clone(),
+            req: Some(req),
+            retry: retry,
+            recorder: Recorder::new(),
+            timeout: self.timeout,
+            sleep: None,
+        }
+    }
+
+    /// Returns a future that fails with `e`.
+    fn fail(&self, e: Error) -> ResponseFuture<C> {
+        ResponseFuture {
+            state: State::Failed(Some(e)),
+            connector: self.connector.clone(),
+            req: None,
+            retry: None,
+            recorder: Recorder::new(),
+            timeout: None,
+            sleep: None,
+        }
+    }
+}
+
+/// State of a `ResponseFuture`.
+enum State<T> {
+    Connecting(ConnectFuture<T>),
+    Sending(Box<SendRequest<T>>),
+    Failed(Option<Error>),
+}
+
+/// Future that resolves to an HTTP response.
+pub struct ResponseFuture<C: Connect> {
+    state: State<C::Io>,
+    connector: Arc<C>,
+    /// Request to send once connected.
+    req: Option<HttpRequest>,
+    /// Copy of the request, sent again to answer a Digest challenge.
+    retry: Option<HttpRequest>,
+    recorder: Recorder,
+    /// Timeout of the request, whose timer starts when the future is first polled.
+    timeout: Option<Duration>,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl<C: Connect> Future for ResponseFuture<C> {
+    type Output = io::Result<HttpResponse>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<HttpResponse>> {
+        let this = self.get_mut();
+        if let Some(timeout) = this.timeout.take() {
+            this.sleep = Some(Box::pin(time::sleep(timeout)));
+        }
+        if let Some(ref mut sleep) = this.sleep {
+            if sleep.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(Err(Error::new(ErrorKind::TimedOut, "request timed out")));
+            }
+        }
+
+        loop {
+            this.state = match this.state {
+                State::Connecting(ref mut connection) => {
+                    let io = ready!(connection.as_mut().poll(cx))?;
+                    this.recorder.connected();
+                    State::Sending(Box::new(this.req.take().unwrap().send(io)))
+                }
+                State::Sending(ref mut sending) => {
+                    let (res, _io) = ready!(Pin::new(&mut **sending).poll(cx))?;
+                    let mut res = res.ok_or(Error::new(ErrorKind::UnexpectedEof, "connection closed before response"))?;
+                    this.recorder.first_byte();
+                    this.recorder.completed();
+                    if res.status() == 401 {
+                        if let Some(req) = this.retry.take().and_then(|req| req.digest_retry(&res)) {
+                            debug!("answering the Digest challenge of {}", ::redact_url(&req.url));
+                            this.recorder = Recorder::new();
+                            this.state = State::Connecting(this.connector.connect(&req.url));
+                            this.req = Some(req);
+                            continue;
+                        }
+                    }
+                    response::set_timings(&mut res, this.recorder.timings());
+                    return Poll::Ready(Ok(res));
+                }
+                State::Failed(ref mut e) => return Poll::Ready(Err(e.take().expect("future polled after failure"))),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+    use std::net::{SocketAddr, TcpListener};
+    use std::thread::{self, JoinHandle};
+    use std::time::Duration;
+
+    use futures03::{SinkExt, StreamExt};
+
+    use tokio::net::TcpStream;
+    use tokio::runtime::{Builder, Runtime};
+
+    use tokio_util::codec::Framed;
+
+    use {HttpCodec, HttpRequest, Method};
+    use super::Client;
+
+    /// Accepts one connection, and answers each request head received on it with the
+    /// next response. Resolves to the request heads.
+    fn serve(responses: Vec<&'static [u8]>) -> (SocketAddr, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let (mut buf, mut heads) = (vec![], vec![]);
+            for response in responses {
+                let end = loop {
+                    if let Some(end) = buf.windows(4).position(|window| window == b"\r\n\r\n") {
+                        break end + 4;
+                    }
+                    let mut data = [0; 1024];
+                    let len = stream.read(&mut data).unwrap();
+                    buf.extend_from_slice(&data[..len]);
+                };
+                heads.push(String::from_utf8(buf.drain(..end).collect()).unwrap());
+                stream.write_all(response).unwrap();
+            }
+            heads
+        });
+        (addr, server)
+    }
+
+    fn runtime() -> Runtime {
+        Builder::new_current_thread().enable_all().build().unwrap()
+    }
+
+    #[test]
+    fn send() {
+        let (addr, server) = serve(vec![
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n",
+        ]);
+        let runtime = runtime();
+        let io = runtime.block_on(TcpStream::connect(addr)).unwrap();
+
+        let req = HttpRequest::get(format!("http://{}/tokio", addr)).unwrap();
+        let (res, io) = runtime.block_on(req.send(io)).unwrap();
+        assert_eq!(res.unwrap().get_body(), b"hello");
+
+        // the connection is handed back for the next request
+        let req = HttpRequest::new(Method::Head, format!("http://{}/tokio", addr)).unwrap();
+        let (res, _io) = runtime.block_on(req.send(io)).unwrap();
+        let res = res.unwrap();
+        assert_eq!((res.status(), res.get_body()), (200, &b""[..]));
+
+        let heads = server.join().unwrap();
+        assert!(heads[0].starts_with("GET /tokio HTTP/1.1\r\n"));
+        assert!(heads[1].starts_with("HEAD /tokio HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn codec() {
+        let (addr, server) = serve(vec![
+            b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n",
+            b"HTTP/1.1 201 Created\r\nContent-Length: 2\r\n\r\nok",
+        ]);
+        let runtime = runtime();
+        let io = runtime.block_on(TcpStream::connect(addr)).unwrap();
+        let mut framed = Framed::new(io, HttpCodec::new());
+
+        let url = format!("http://{}/", addr);
+        runtime.block_on(framed.feed(HttpRequest::new(Method::Head, &url).unwrap())).unwrap();
+        runtime.block_on(framed.send(HttpRequest::post(&url, "ok").unwrap())).unwrap();
+        let res = runtime.block_on(framed.next()).unwrap().unwrap();
+        assert_eq!((res.status(), res.get_body()), (200, &b""[..]));
+        let res = runtime.block_on(framed.next()).unwrap().unwrap();
+        assert_eq!((res.status(), res.get_body()), (201, &b"ok"[..]));
+        assert!(server.join().unwrap()[1].starts_with("POST / HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn client() {
+        let (addr, server) = serve(vec![b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello"]);
+        let runtime = runtime();
+        let res = runtime.block_on(Client::new().get(format!("http://{}/", addr))).unwrap();
+        assert_eq!(res.get_body(), b"hello");
+        assert!(res.timings().complete().is_some());
+        server.join().unwrap();
+
+        // the server never answers
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = Client::new().timeout(Duration::from_millis(50));
+        let e = runtime.block_on(client.get(format!("http://{}/", addr))).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::TimedOut);
+        drop(listener);
+    }
+}
@@ -0,0 +1,149 @@
+//! Protocol upgrades (101 Switching Protocols).
+
+use std::cmp;
+use std::io::{self, Error, ErrorKind, Read, Write};
+
+use futures::{Future, Poll, future};
+
+use bytes::BytesMut;
+
+use tokio_io::{IoFuture, AsyncRead, AsyncWrite};
+
+use body;
+use {HttpRequest, HttpResponse};
+
+/// Connection switched to another protocol.
+///
+/// Data the server sent right after the 101 response, and that was read along with
+/// it, is returned first by reads; writes go directly to the connection.
+pub struct Upgraded<T> {
+    io: T,
+    buf: BytesMut,
+}
+
+impl<T> Upgraded<T> {
+    /// Returns a reference to the underlying connection.
+    pub fn get_ref(&self) -> &T {
+        &self.io
+    }
+
+    /// Returns a mutable reference to the underlying connection.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.io
+    }
+
+    /// Returns the underlying connection, and the data already read from it that
+    /// has not been consumed yet.
+    pub fn into_parts(self) -> (T, BytesMut) {
+        (self.io, self.buf)
+    }
+}
+
+impl<T: Read> Read for Upgraded<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buf.is_empty() {
+            return self.io.read(buf);
+        }
+
+        let len = cmp::min(buf.len(), self.buf.len());
+        buf[..len].copy_from_slice(&self.buf.split_to(len));
+        Ok(len)
+    }
+}
+
+impl<T: Write> Write for Upgraded<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.io.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for Upgraded<T> {}
+
+impl<T: AsyncWrite> AsyncWrite for Upgraded<T> {
+    fn shutdown(&mut self) -> Poll<(), Error> {
+        self.io.shutdown()
+    }
+}
+
+/// Returns a future that sends the given request, which must have an `Upgrade`
+/// header, over `io`, and resolves to the 101 response and the upgraded connection.
+///
+/// Fails if the server does not switch to the protocol requested.
+pub fn upgrade<T>(req: HttpRequest, io: T) -> IoFuture<(HttpResponse, Upgraded<T>)>
+where
+    T: 'static + AsyncRead + AsyncWrite + Send,
+{
+    let protocol = match req.get_header("Upgrade") {
+        Some(protocol) => protocol.to_string(),
+        None => return Box::new(future::err(Error::new(ErrorKind::InvalidInput, "no Upgrade header"))),
+    };
+
+    Box::new(req.write_to(io).and_then(body::read_head).and_then(move |(res, io, buf)| {
+        check(&res, &protocol)?;
+        let upgraded = Upgraded {
+            io: io,
+            buf: buf,
+        };
+        Ok((res, upgraded))
+    }))
+}
+
+/// Checks that `res` switches to one of the given comma-separated protocols.
+fn check(res: &HttpResponse, protocols: &str) -> Result<(), Error> {
+    if res.status() != 101 {
+        let msg = format!("server did not switch protocols ({})", res.status());
+        return Err(Error::other(msg));
+    }
+
+    let accepted = res["Upgrade"].as_ref().map_or(false, |upgrade| {
+        protocols.split(',').any(|protocol| upgrade.trim().eq_ignore_ascii_case(protocol.trim()))
+    });
+    if !accepted || !res.has("Connection", "upgrade") {
+        return Err(Error::new(ErrorKind::InvalidData, "invalid 101 response"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read};
+    use std::sync::{Arc, Mutex};
+
+    use futures::Future;
+
+    use mock::MockConnector;
+    use {Client, HttpRequest};
+
+    #[test]
+    fn upgrade() {
+        let output = Arc::new(Mutex::new(vec![]));
+        let client = Client::with_connector(MockConnector::new(
+            vec![
+                b"HTTP/1.1 101 Switching Protocols\r\nConnection: upgrade\r\nUpgrade: foo/2\r\n\r\nhello",
+                b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n",
+            ],
+            &output,
+        ));
+
+        let req = || HttpRequest::get("http://example.com/").unwrap().upgrade("foo/2");
+        let (res, mut io) = client.upgrade(req()).wait().unwrap();
+        assert_eq!(res.status(), 101);
+        assert_eq!(
+            &output.lock().unwrap()[..],
+            &b"GET / HTTP/1.1\r\nHost: example.com\r\nConnection: upgrade\r\nUpgrade: foo/2\r\n\r\n"[..]
+        );
+
+        let mut buf = [0; 3];
+        assert_eq!(io.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf, b"hel");
+        let (_, rest) = io.into_parts();
+        assert_eq!(&rest[..], b"lo");
+
+        let e = client.upgrade(req()).wait().err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::Other);
+    }
+}
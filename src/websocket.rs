@@ -0,0 +1,414 @@
+//! WebSocket client (RFC 6455).
+//!
+//! The opening handshake upgrades an HTTP connection, after which frames are
+//! exchanged with a `WebSocketCodec`:
+//!
+//! ```no-run
+//! let req = HttpRequest::get("ws://echo.example.com/").unwrap();
+//! let ws = client.websocket(req).and_then(|(_res, ws)| ws.send(Frame::text("hello")));
+//! ```
+
+use std::io::{Error, ErrorKind};
+use std::str;
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use futures::Future;
+
+use tokio_io::{IoFuture, AsyncRead, AsyncWrite};
+#[allow(deprecated)]
+use tokio_io::codec::{Decoder, Encoder, Framed};
+
+use base64;
+use rand;
+use sha1::{Digest, Sha1};
+
+use upgrade::{self, Upgraded};
+use {HttpRequest, HttpResponse};
+
+/// WebSocket connection, a stream and sink of frames.
+#[allow(deprecated)]
+pub type WebSocket<T> = Framed<Upgraded<T>, WebSocketCodec>;
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Returns a new random value for the `Sec-WebSocket-Key` header.
+fn key() -> String {
+    let bytes = (0..16).map(|_| rand::random::<u8>()).collect::<Vec<_>>();
+    base64::encode(&bytes)
+}
+
+/// Returns the `Sec-WebSocket-Accept` value that proves a server received the given key.
+pub fn accept(key: &str) -> String {
+    let mut sha1 = Sha1::default();
+    sha1.input(key.as_bytes());
+    sha1.input(GUID.as_bytes());
+    base64::encode(&sha1.result())
+}
+
+/// Returns a future that performs the opening handshake of a WebSocket over `io`,
+/// using the given GET request, and resolves to the 101 response and the WebSocket.
+///
+/// Fails if the server does not accept the handshake.
+pub fn handshake<T>(req: HttpRequest, io: T) -> IoFuture<(HttpResponse, WebSocket<T>)>
+where
+    T: 'static + AsyncRead + AsyncWrite + Send,
+{
+    let key = key();
+    let expected = accept(&key);
+    let req = req.upgrade("websocket")
+        .set_header("Sec-WebSocket-Version", "13")
+        .set_header("Sec-WebSocket-Key", key);
+
+    Box::new(upgrade::upgrade(req, io).and_then(move |(res, io)| {
+        let accepted = res["Sec-WebSocket-Accept"].as_ref().map_or(false, |accept| accept.trim() == expected);
+        if !accepted {
+            return Err(Error::new(ErrorKind::InvalidData, "invalid Sec-WebSocket-Accept"));
+        }
+
+        #[allow(deprecated)]
+        let ws = io.framed(WebSocketCodec::new());
+        Ok((res, ws))
+    }))
+}
+
+/// Opcode of a frame.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    fn from_u8(opcode: u8) -> Option<Opcode> {
+        match opcode {
+            0x0 => Some(Opcode::Continuation),
+            0x1 => Some(Opcode::Text),
+            0x2 => Some(Opcode::Binary),
+            0x8 => Some(Opcode::Close),
+            0x9 => Some(Opcode::Ping),
+            0xA => Some(Opcode::Pong),
+            _ => None,
+        }
+    }
+
+    fn as_u8(&self) -> u8 {
+        match *self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xA,
+        }
+    }
+
+    /// Returns true for Close, Ping and Pong frames.
+    pub fn is_control(&self) -> bool {
+        self.as_u8() >= 0x8
+    }
+}
+
+/// WebSocket frame.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Frame {
+    fin: bool,
+    opcode: Opcode,
+    payload: Bytes,
+}
+
+impl Frame {
+    /// Creates a new frame; `fin` is false for all but the last frame of a fragmented message.
+    pub fn new<B: Into<Bytes>>(fin: bool, opcode: Opcode, payload: B) -> Frame {
+        Frame {
+            fin: fin,
+            opcode: opcode,
+            payload: payload.into(),
+        }
+    }
+
+    /// Creates a text frame.
+    pub fn text<S: Into<String>>(text: S) -> Frame {
+        Frame::new(true, Opcode::Text, text.into())
+    }
+
+    /// Creates a binary frame.
+    pub fn binary<B: Into<Bytes>>(data: B) -> Frame {
+        Frame::new(true, Opcode::Binary, data)
+    }
+
+    /// Creates a ping frame.
+    pub fn ping<B: Into<Bytes>>(data: B) -> Frame {
+        Frame::new(true, Opcode::Ping, data)
+    }
+
+    /// Creates a pong frame, which answers a ping with the same data.
+    pub fn pong<B: Into<Bytes>>(data: B) -> Frame {
+        Frame::new(true, Opcode::Pong, data)
+    }
+
+    /// Creates a close frame with the given status code and reason.
+    pub fn close(code: u16, reason: &str) -> Frame {
+        let mut payload = BytesMut::with_capacity(2 + reason.len());
+        payload.put_u16_be(code);
+        payload.put_slice(reason.as_bytes());
+        Frame::new(true, Opcode::Close, payload.freeze())
+    }
+
+    /// Returns true if this frame is the last of its message.
+    pub fn is_final(&self) -> bool {
+        self.fin
+    }
+
+    /// Returns the opcode of this frame.
+    pub fn opcode(&self) -> Opcode {
+        self.opcode
+    }
+
+    /// Returns the payload of this frame.
+    pub fn payload(&self) -> &Bytes {
+        &self.payload
+    }
+
+    /// Returns the payload of this frame as text, if it is valid UTF-8.
+    pub fn as_text(&self) -> Option<&str> {
+        str::from_utf8(&self.payload).ok()
+    }
+
+    /// Returns the status code and reason of a close frame, if any.
+    pub fn close_reason(&self) -> Option<(u16, &str)> {
+        if self.opcode != Opcode::Close || self.payload.len() < 2 {
+            return None;
+        }
+        let code = (u16::from(self.payload[0]) << 8) | u16::from(self.payload[1]);
+        str::from_utf8(&self.payload[2..]).ok().map(|reason| (code, reason))
+    }
+}
+
+/// Codec of the frames of the client side of a WebSocket.
+///
+/// Frames sent are masked, and frames received must not be.
+#[derive(Debug)]
+pub struct WebSocketCodec {
+    max_payload: u64,
+}
+
+impl WebSocketCodec {
+    /// Creates a new codec that accepts payloads of up to 16 MiB.
+    pub fn new() -> WebSocketCodec {
+        WebSocketCodec { max_payload: 16 << 20 }
+    }
+
+    /// Sets the maximum length of the payload of the frames received.
+    pub fn max_payload(mut self, length: u64) -> WebSocketCodec {
+        self.max_payload = length;
+        self
+    }
+}
+
+impl Default for WebSocketCodec {
+    fn default() -> WebSocketCodec {
+        WebSocketCodec::new()
+    }
+}
+
+fn invalid(msg: &'static str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+impl Decoder for WebSocketCodec {
+    type Item = Frame;
+    type Error = Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Frame>, Error> {
+        if buf.len() < 2 {
+            return Ok(None);
+        }
+
+        let (first, second) = (buf[0], buf[1]);
+        if first & 0x70 != 0 {
+            return Err(invalid("reserved bits set"));
+        }
+        if second & 0x80 != 0 {
+            return Err(invalid("masked frame from server"));
+        }
+        let fin = first & 0x80 != 0;
+        let opcode = Opcode::from_u8(first & 0x0F).ok_or_else(|| invalid("unknown opcode"))?;
+
+        let (header, length) = match second & 0x7F {
+            126 => {
+                if buf.len() < 4 {
+                    return Ok(None);
+                }
+                (4, (u64::from(buf[2]) << 8) | u64::from(buf[3]))
+            }
+            127 => {
+                if buf.len() < 10 {
+                    return Ok(None);
+                }
+                let length = buf[2..10].iter().fold(0, |length, &byte| (length << 8) | u64::from(byte));
+                if length >> 63 != 0 {
+                    return Err(invalid("invalid payload length"));
+                }
+                (10, length)
+            }
+            length => (2, u64::from(length)),
+        };
+
+        if opcode.is_control() && (!fin || length > 125) {
+            return Err(invalid("invalid control frame"));
+        }
+        if length > self.max_payload {
+            return Err(invalid("frame too large"));
+        }
+        if (buf.len() as u64) < header as u64 + length {
+            return Ok(None);
+        }
+
+        buf.split_to(header);
+        let payload = buf.split_to(length as usize).freeze();
+        Ok(Some(Frame::new(fin, opcode, payload)))
+    }
+}
+
+impl Encoder for WebSocketCodec {
+    type Item = Frame;
+    type Error = Error;
+
+    fn encode(&mut self, frame: Frame, buf: &mut BytesMut) -> Result<(), Error> {
+        let length = frame.payload.len();
+        buf.reserve(14 + length);
+
+        let fin = if frame.fin { 0x80 } else { 0 };
+        buf.put_u8(fin | frame.opcode.as_u8());
+        if length < 126 {
+            buf.put_u8(0x80 | length as u8);
+        } else if length <= 0xFFFF {
+            buf.put_u8(0x80 | 126);
+            buf.put_u16_be(length as u16);
+        } else {
+            buf.put_u8(0x80 | 127);
+            buf.put_u64_be(length as u64);
+        }
+
+        let mask = [rand::random::<u8>(), rand::random(), rand::random(), rand::random()];
+        buf.put_slice(&mask);
+        for (i, byte) in frame.payload.iter().enumerate() {
+            buf.put_u8(byte ^ mask[i % 4]);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use bytes::BytesMut;
+
+    use tokio_core::reactor::Core;
+    use tokio_io::codec::{Decoder, Encoder};
+
+    use futures::{Future, Sink, Stream};
+
+    use {Client, HttpRequest};
+    use super::{accept, Frame, Opcode, WebSocketCodec};
+
+    #[test]
+    fn accept_key() {
+        assert_eq!(accept("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn codec() {
+        let mut codec = WebSocketCodec::new();
+
+        // unmasked "Hello", in two fragments
+        let mut buf = BytesMut::from(&b"\x01\x03Hel\x80\x02l"[..]);
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Frame::new(false, Opcode::Text, "Hel")));
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        buf.extend_from_slice(b"o");
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Frame::new(true, Opcode::Continuation, "lo")));
+        assert!(buf.is_empty());
+
+        let mut buf = BytesMut::from(&b"\x88\x05\x03\xe8bye"[..]);
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap().close_reason(), Some((1000, "bye")));
+
+        let mut buf = BytesMut::from(vec![0x82, 126, 0x01, 0x00]);
+        buf.extend_from_slice(&[7; 256]);
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap().payload().len(), 256);
+
+        // masked, fragmented control and oversized frames are rejected
+        assert!(codec.decode(&mut BytesMut::from(&b"\x81\x80abcd"[..])).is_err());
+        assert!(codec.decode(&mut BytesMut::from(&b"\x09\x00"[..])).is_err());
+        assert!(codec.decode(&mut BytesMut::from(&b"\xc1\x00"[..])).is_err());
+        let mut codec = WebSocketCodec::new().max_payload(4);
+        assert!(codec.decode(&mut BytesMut::from(&b"\x82\x05hello"[..])).is_err());
+
+        let mut buf = BytesMut::new();
+        codec.encode(Frame::text("Hello"), &mut buf).unwrap();
+        assert_eq!(&buf[..2], b"\x81\x85");
+        let mask = [buf[2], buf[3], buf[4], buf[5]];
+        let payload = buf[6..].iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]).collect::<Vec<_>>();
+        assert_eq!(payload, b"Hello");
+
+        let mut buf = BytesMut::new();
+        codec.encode(Frame::binary(vec![0; 70_000]), &mut buf).unwrap();
+        assert_eq!(&buf[..10], b"\x82\xff\x00\x00\x00\x00\x00\x01\x11\x70");
+        assert_eq!(buf.len(), 14 + 70_000);
+    }
+
+    #[test]
+    fn handshake() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = vec![];
+            let mut buf = [0; 256];
+            while !request.ends_with(b"\r\n\r\n") {
+                let len = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..len]);
+            }
+            let request = String::from_utf8(request).unwrap();
+            assert!(request.contains("\r\nUpgrade: websocket\r\n"));
+            assert!(request.contains("\r\nSec-WebSocket-Version: 13\r\n"));
+            let key = request
+                .lines()
+                .find(|line| line.starts_with("Sec-WebSocket-Key: "))
+                .unwrap()[19..]
+                .to_string();
+
+            // the first frame is likely read along with the response
+            write!(
+                stream,
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                 Sec-WebSocket-Accept: {}\r\n\r\n",
+                accept(&key)
+            ).unwrap();
+            stream.write_all(b"\x81\x02hi").unwrap();
+
+            let mut frame = [0; 8];
+            stream.read_exact(&mut frame).unwrap();
+            assert_eq!(&frame[..2], b"\x82\x82");
+            assert_eq!([frame[6] ^ frame[2], frame[7] ^ frame[3]], [1, 2]);
+        });
+
+        let mut core = Core::new().unwrap();
+        let client = Client::new(&core.handle());
+        let req = HttpRequest::get(format!("ws://127.0.0.1:{}/chat", port)).unwrap();
+        let (res, ws) = core.run(client.websocket(req)).unwrap();
+        assert_eq!(res.status(), 101);
+
+        let (frame, ws) = core.run(ws.into_future().map_err(|(e, _)| e)).unwrap();
+        assert_eq!(frame.unwrap().as_text(), Some("hi"));
+        core.run(ws.send(Frame::binary(vec![1, 2]))).unwrap();
+        server.join().unwrap();
+    }
+}